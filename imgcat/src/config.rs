@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// Defaults for the command line flags, each one optional.
+///
+/// ```toml
+/// protocol = "auto"
+/// order = ["kitty", "sixel"]
+/// fit = "screen"
+/// filter = "lanczos3"
+/// background = "#202020"
+/// max_width = "80"
+/// max_height = "50%"
+/// color_depth = "256"
+///
+/// [terminal.wezterm]
+/// protocol = "iterm"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Protocol, or comma separated list of them, as `--protocol` takes.
+    pub protocol: Option<String>,
+    /// Protocols to try in order when `protocol` is not set.
+    pub order: Option<Vec<String>>,
+    pub fit: Option<String>,
+    pub filter: Option<String>,
+    pub background: Option<String>,
+    pub max_width: Option<String>,
+    pub max_height: Option<String>,
+    pub color_depth: Option<String>,
+}
+
+impl Settings {
+    /// These settings with those `other` sets replacing them.
+    fn merge(self, other: Settings) -> Settings {
+        Settings {
+            protocol: other.protocol.or(self.protocol),
+            order: other.order.or(self.order),
+            fit: other.fit.or(self.fit),
+            filter: other.filter.or(self.filter),
+            background: other.background.or(self.background),
+            max_width: other.max_width.or(self.max_width),
+            max_height: other.max_height.or(self.max_height),
+            color_depth: other.color_depth.or(self.color_depth),
+        }
+    }
+}
+
+/// The config file, read by [`load`].
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    settings: Settings,
+    /// Settings for a terminal, keyed by the name it is identified by.
+    #[serde(default)]
+    terminal: HashMap<String, Settings>,
+    /// Keys neither of the above knows, collected here to be reported: serde cannot deny
+    /// them itself through a flattened struct.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Config {
+    /// The settings for every terminal, without asking the terminal who it is.
+    pub fn common(self) -> Settings {
+        self.settings
+    }
+
+    /// The settings for the terminal the program runs in, which is asked who it is
+    /// when the file has settings for some terminal.
+    pub fn for_terminal(mut self) -> Settings {
+        if self.terminal.is_empty() {
+            return self.settings;
+        }
+        let overrides = termimg::terminal::identify().and_then(|t| self.terminal.remove(&t.name));
+        match overrides {
+            Some(overrides) => self.settings.merge(overrides),
+            None => self.settings,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/termimg/config.toml`, `~/.config/termimg/config.toml` by default.
+pub fn path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("termimg").join("config.toml"))
+}
+
+/// Reads the config file. A missing file sets nothing.
+pub fn load() -> Result<Config, String> {
+    let path = match path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(Config::default()),
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let config: Config = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if let Some(key) = config.unknown.keys().next() {
+        return Err(format!("{}: unknown setting `{}`", path.display(), key));
+    }
+    Ok(config)
+}
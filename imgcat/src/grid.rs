@@ -0,0 +1,178 @@
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use image::GenericImageView;
+use termimg::graphic::{guess_size, Graphic, TerminalSize};
+use termimg::options::DisplayOptions;
+use termimg::utils::{get_image_with_options, prepare_decoded};
+
+/// Width in cells a column gets when the number of columns is not given.
+const DEFAULT_COLUMN_WIDTH: u16 = 24;
+
+/// How a grid of thumbnails is laid out on the terminal.
+struct Layout {
+    columns: u16,
+    /// Columns of cells taken by one grid column, the gap after the thumbnail included.
+    cell_cols: u16,
+    thumb_cols: u16,
+    thumb_rows: u16,
+    /// Size of the box a thumbnail is scaled to fit, in the viewer's pixels.
+    thumb_width: u32,
+    thumb_height: u32,
+}
+
+impl Layout {
+    fn new(size: &TerminalSize, columns: Option<u16>) -> Layout {
+        let columns = columns
+            .unwrap_or(size.cols / DEFAULT_COLUMN_WIDTH)
+            .clamp(1, size.cols.max(1));
+        let cell_cols = size.cols / columns;
+        let thumb_cols = cell_cols.saturating_sub(1).max(1);
+        let (cell_width, cell_height) = size.cell_size();
+        // A square box, but never taller than the screen less the caption line.
+        let thumb_rows = ((thumb_cols as f32 * cell_width / cell_height).round() as u16)
+            .clamp(1, size.rows.saturating_sub(2).max(1));
+        Layout {
+            columns,
+            cell_cols,
+            thumb_cols,
+            thumb_rows,
+            thumb_width: (thumb_cols as f32 * cell_width) as u32,
+            thumb_height: (thumb_rows as f32 * cell_height) as u32,
+        }
+    }
+}
+
+/// Shows the images at `paths` as a grid of thumbnails with their names underneath,
+/// followed by the dimensions of the decoded files in pixels when `dimensions` is set.
+///
+/// Returns whether every image could be shown.
+pub fn show_grid(
+    viewer: &dyn Graphic,
+    paths: &[String],
+    opts: &DisplayOptions,
+    columns: Option<u16>,
+    dimensions: bool,
+) -> bool {
+    let size = match viewer.size().or_else(guess_size) {
+        Some(size) => size,
+        None => {
+            eprintln!("imgcat: unable to tell the terminal size");
+            return false;
+        }
+    };
+    let layout = Layout::new(&size, columns);
+    let mut opts = opts.clone();
+    opts.size = None;
+    let mut ok = true;
+    for row in paths.chunks(layout.columns as usize) {
+        ok &= show_row(viewer, row, &opts, &layout, dimensions);
+    }
+    ok
+}
+
+fn show_row(
+    viewer: &dyn Graphic,
+    paths: &[String],
+    opts: &DisplayOptions,
+    layout: &Layout,
+    dimensions: bool,
+) -> bool {
+    let mut stdout = stdout();
+    // Scroll first so that drawing the row never does.
+    let height = layout.thumb_rows + 1;
+    print!("{}\x1b[{}A", "\n".repeat(height as usize), height);
+    let mut ok = true;
+    let mut sizes = vec![];
+    for (i, path) in paths.iter().enumerate() {
+        move_to_column(i as u16 * layout.cell_cols);
+        let shown = get_image_with_options(path, opts)
+            .map_err(|e| e.to_string())
+            .and_then(|img| {
+                // The file's own size, before cropping, transforms and scaling.
+                let size = img.dimensions();
+                let img = prepare_decoded(img, opts)?;
+                let thumb = img.thumbnail(layout.thumb_width, layout.thumb_height);
+                viewer.display_in_place(&thumb).map_err(|e| e.to_string())?;
+                Ok(size)
+            });
+        match shown {
+            Ok(size) => sizes.push(Some(size)),
+            Err(e) => {
+                eprintln!("imgcat: {}: {}", path, e);
+                sizes.push(None);
+                ok = false;
+            }
+        }
+    }
+    print!("\x1b[{}B", layout.thumb_rows);
+    for (i, (path, size)) in paths.iter().zip(sizes).enumerate() {
+        move_to_column(i as u16 * layout.cell_cols);
+        let size = if dimensions { size } else { None };
+        print!("{}", caption(path, size, layout.thumb_cols as usize));
+    }
+    println!();
+    let _ = stdout.flush();
+    ok
+}
+
+fn move_to_column(col: u16) {
+    print!("\r");
+    if col > 0 {
+        print!("\x1b[{}C", col);
+    }
+}
+
+/// The file name of `path` followed by `size`, the name cut so that both fit in
+/// `width` columns.
+fn caption(path: &str, size: Option<(u32, u32)>, width: usize) -> String {
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned());
+    let suffix = size
+        .map(|(w, h)| format!(" {}x{}", w, h))
+        .filter(|suffix| suffix.len() < width)
+        .unwrap_or_default();
+    let width = width - suffix.len();
+    if name.chars().count() <= width {
+        name + &suffix
+    } else {
+        let mut cut: String = name.chars().take(width.saturating_sub(1)).collect();
+        cut.push('\u{2026}');
+        cut + &suffix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal(cols: u16, rows: u16) -> TerminalSize {
+        TerminalSize {
+            width: cols * 8,
+            height: rows * 16,
+            cols,
+            rows,
+        }
+    }
+
+    #[test]
+    fn layout_splits_the_width_into_columns() {
+        let layout = Layout::new(&terminal(100, 30), None);
+        assert_eq!((layout.columns, layout.cell_cols, layout.thumb_cols), (4, 25, 24));
+        assert_eq!(layout.thumb_rows, 12);
+        assert_eq!((layout.thumb_width, layout.thumb_height), (192, 192));
+        let layout = Layout::new(&terminal(100, 30), Some(500));
+        assert_eq!((layout.columns, layout.cell_cols, layout.thumb_cols), (100, 1, 1));
+        // Never taller than the screen less the caption line.
+        assert_eq!(Layout::new(&terminal(100, 5), Some(1)).thumb_rows, 3);
+    }
+
+    #[test]
+    fn captions_are_cut_to_fit() {
+        assert_eq!(caption("dir/a.png", Some((640, 480)), 20), "a.png 640x480");
+        assert_eq!(caption("dir/long-name.png", Some((640, 480)), 12), "lon\u{2026} 640x480");
+        assert_eq!(caption("a.png", Some((1, 1)), 4), "a.p\u{2026}");
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use termimg::utils::sniff_file;
+
+/// The images found in one directory, in name order.
+pub struct Listing {
+    pub dir: String,
+    pub images: Vec<String>,
+}
+
+/// Lists the images in `dir`, then those of its subdirectories when `recursive` is set,
+/// one listing per directory like `ls -R`.
+///
+/// Hidden entries are skipped, and so are files whose leading bytes do not look like
+/// an image this build decodes. Subdirectories that cannot be read are reported and left out.
+pub fn list_images(dir: &Path, recursive: bool) -> io::Result<Vec<Listing>> {
+    let mut listings = vec![];
+    let mut subdirs = vec![];
+    let mut images = vec![];
+    for path in sorted_entries(dir)? {
+        if path.is_dir() {
+            subdirs.push(path);
+        } else if sniff_file(&path).is_ok_and(|kind| kind.is_some_and(|k| k.decodable())) {
+            images.push(path.to_string_lossy().into_owned());
+        }
+    }
+    listings.push(Listing {
+        dir: dir.to_string_lossy().into_owned(),
+        images,
+    });
+    if recursive {
+        for subdir in subdirs {
+            match list_images(&subdir, true) {
+                Ok(mut sub) => listings.append(&mut sub),
+                Err(e) => eprintln!("imgcat: {}: {}", subdir.display(), e),
+            }
+        }
+    }
+    Ok(listings)
+}
+
+fn sorted_entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with('.') {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
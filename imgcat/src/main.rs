@@ -1,23 +1,39 @@
 extern crate docopt;
+extern crate glob;
+extern crate signal_hook;
+extern crate termion;
 extern crate image;
-extern crate infer;
+extern crate termimg;
 extern crate resvg;
 extern crate usvg;
+extern crate toml;
 #[macro_use]
 extern crate serde_derive;
 // extern crate seek_bufread;
 // extern crate terminal_size;
 
-use std::{
-    io::{BufReader, Cursor, Read},
-    path::Path,
-};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
 
 use docopt::Docopt;
 use image::*;
-use infer::Type;
+use std::sync::atomic::{AtomicBool, Ordering};
+use termimg::animation::{decode_frames, stop_on_ctrl_c, Player};
+use termimg::graphic::{Canvas, Graphic, TerminalSize};
+use termimg::options::{parse_filter, parse_scale, Background, Dimension, DisplayOptions};
+use config::Settings;
+use termimg::protocol::{resolve, Preference};
+use termimg::stream::ImageStream;
+use termimg::transform::{self, Transform};
 // use terminal_size::{terminal_size, Height, Width};
 
+mod config;
+mod grid;
+mod listing;
+mod viewer;
+mod watch;
+
 // mod apc;
 // mod graphic;
 // mod iterm;
@@ -33,33 +49,111 @@ const USAGE: &'static str = "
     termimg : display image from <file> in an terminal
 
     Usage:
-      termimg <file> [--protocol <protocol>]
-      termimg --stdio
+      termimg [options] [<file>...]
+      termimg [options] --stdio
+      termimg [options] --stream
       termimg --list-protocol
 
     Options:
-      --protocol <protocol>         One of kitty,mosaic,auto, [Default:auto]
+      --protocol <protocol>         One of kitty,iterm,sixel,mosaic or auto, the default,
+                                    or a list of them to try in order, such as
+                                    sixel,mosaic; TERMIMG_PROTOCOL sets it too
+      --background <bg>             Blend transparent images over a #rrggbb colour,
+                                    checkerboard or the terminal background
+      --stdio                       Read the image from stdin, as does a file named -
+      --width <w>                   Width in cells, pixels (300px) or percent (50%)
+      --height <h>                  Height in cells, pixels (300px) or percent (50%)
+      --scale <x>                   Scale images by this factor
+      --fit <mode>                  Fit images to the terminal width, screen or none
+      --no-upscale                  Never scale images above their own size
+      --filter <filter>             Scaling filter: nearest, triangle, catmull-rom,
+                                    gaussian or lanczos3
+      -o --output <file>            Write the escape sequences to a file to replay later,
+                                    sized with --width and --height in cells or pixels;
+                                    percentages of the config are not applied
+      --crop <region>               Show only a WxH+X+Y region of the image, in pixels
+      --rotate <deg>                Rotate images clockwise by this many degrees
+      --flip <axis>                 Mirror images: horizontal, vertical or both
+      --zoom <x>                    Zoom in by this factor, at least 1
+      --pan <x,y>                   Top left corner of the zoomed part, in pixels
+      --grayscale                   Drop the colours
+      --invert                      Invert the colours
+      --brightness <n>              Add this to every colour, -255 to 255
+      --contrast <pct>              Raise the contrast by this percentage, or lower it
+      --cursor <policy>             Leave the cursor after the image, at its start or
+                                    below it
+      --caption                     Print the file name under each image
+      --grid                        Show the images as a grid of thumbnails
+      --columns <n>                 Number of columns of the grid
+      -r --recursive                List the images of subdirectories too
+      -i --interactive              Browse the images full screen
+      --stream                      Show each image of a stream read from stdin in turn:
+                                    PNGs, JPEGs (MJPEG) or length-prefixed images
+      --watch                       Redraw the image each time the file changes
+      --animate                     Play animated GIF, PNG and WebP images
+      --loops <n>                   Times to play animations, 0 for ever, as often as
+                                    the file asks by default
+      --speed <x>                   Animation speed factor [default: 1.0]
       --list-protocol               Show protocols
 ";
 
+/// The file name standing for stdin.
+const STDIN: &str = "-";
+
 #[derive(Debug, Deserialize)]
 struct Args {
     flag_protocol: Option<String>,
+    flag_background: Option<String>,
     flag_list_protocol: Option<bool>,
-    arg_file: Option<String>,
+    flag_stdio: bool,
+    flag_output: Option<String>,
+    flag_width: Option<String>,
+    flag_height: Option<String>,
+    flag_scale: Option<String>,
+    flag_fit: Option<String>,
+    flag_no_upscale: bool,
+    flag_filter: Option<String>,
+    flag_crop: Option<String>,
+    flag_rotate: Option<f32>,
+    flag_flip: Option<String>,
+    flag_zoom: Option<String>,
+    flag_pan: Option<String>,
+    flag_grayscale: bool,
+    flag_invert: bool,
+    flag_brightness: Option<i32>,
+    flag_contrast: Option<f32>,
+    flag_cursor: Option<String>,
+    flag_caption: bool,
+    flag_grid: bool,
+    flag_columns: Option<u16>,
+    flag_recursive: bool,
+    flag_interactive: bool,
+    flag_stream: bool,
+    flag_watch: bool,
+    flag_animate: bool,
+    flag_loops: Option<u32>,
+    flag_speed: f32,
+    arg_file: Vec<String>,
 }
 
 fn main() {
-    let viewers: Vec<Box<dyn termimg::graphic::Graphic>> = vec![
-        Box::new(termimg::kitty::Kitty {}),
-        Box::new(termimg::iterm::Iterm {}),
-        Box::new(termimg::sixel::Sixel {}),
-        //Box::new(crate::mosaic::Mosaic {}),
-    ];
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
+    let config = config::load().unwrap_or_else(|e| exit_with(&format!("imgcat: {}", e)));
+    // The terminal showing a file written with --output is not this one, so leave this
+    // one alone.
+    let settings = if args.flag_output.is_some() {
+        config.common()
+    } else {
+        config.for_terminal()
+    };
+    let viewers = termimg::protocol::backends(parse(&settings.color_depth).unwrap_or_default());
     if args.flag_list_protocol.is_some() {
+        match termimg::terminal::identify() {
+            Some(t) => println!("terminal is {} {}", t.name, t.version.unwrap_or_default()),
+            None => println!("terminal is unknown"),
+        }
         for g in viewers.iter() {
             println!(
                 "{} is {} supported",
@@ -69,63 +163,425 @@ fn main() {
         }
         return;
     }
-    let viewer = get_viewer(&viewers, args.flag_protocol.unwrap_or("auto".to_owned()))
-        .expect("No viewer specified");
-    let img = if args.arg_file.is_some() {
-        let path = args.arg_file.unwrap();
-        termimg::utils::prepare_img(&path, &viewer.size()).unwrap()
+    let files = if args.flag_stream {
+        vec![]
+    } else {
+        input_files(&args)
+    };
+    let preference = preference(&args, &settings);
+    if let Some(output) = &args.flag_output {
+        // The terminal showing the output is not this one, so leave it alone.
+        let preference = match preference {
+            Preference::Auto => exit_with("imgcat: --output needs a --protocol"),
+            Preference::Ordered(names) => Preference::Forced(names[0].clone()),
+            forced => forced,
+        };
+        let viewer = resolve(&viewers, &preference).unwrap_or_else(|e| exit_with(&e));
+        let mut opts = display_options(&args, &settings, None);
+        if opts.background == Some(Background::Terminal) {
+            // Asking this terminal would be wrong and, over a pipe, can hang: use black,
+            // as the protocols without alpha do.
+            opts.background = Some(Background::Color([0, 0, 0]));
+        }
+        // Nor is there a terminal to take a percentage of: refuse those asked for here
+        // and leave out the config's limits, set for the terminals it is used on.
+        if is_relative(opts.width) || is_relative(opts.height) {
+            exit_with("imgcat: --output needs --width and --height in cells or pixels, not %");
+        }
+        opts.max_width = opts.max_width.filter(|d| !is_relative(Some(*d)));
+        opts.max_height = opts.max_height.filter(|d| !is_relative(Some(*d)));
+        if !write_output(viewer, &files, output, &opts) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let viewer = resolve(&viewers, &preference).unwrap_or_else(|e| exit_with(&e));
+    let opts = display_options(&args, &settings, viewer.size());
+    if args.flag_stream {
+        if let Err(e) = show_stream(viewer, &opts) {
+            exit_with(&format!("imgcat: {}", e));
+        }
+        return;
+    }
+    if args.flag_watch {
+        if files.len() != 1 || files[0] == STDIN {
+            exit_with("imgcat: --watch takes a single file");
+        }
+        let path = &files[0];
+        if let Err(e) = watch::watch(viewer, path, &opts) {
+            exit_with(&format!("imgcat: {}: {}", path, e));
+        }
+        return;
+    }
+    let mut failed = false;
+    let paths = existing_paths(&files, &mut failed);
+    let has_dir = paths.iter().any(|path| Path::new(path).is_dir());
+    if has_dir && !args.flag_interactive && !args.flag_grid {
+        failed |= !show_listings(viewer, &paths, &opts, &args);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let paths = if has_dir {
+        image_paths(paths, args.flag_recursive, &mut failed)
+    } else {
+        paths
+    };
+    if args.flag_interactive {
+        // Images are loaded again as the user moves between them, stdin can only be
+        // read once.
+        if paths.iter().any(|path| path == STDIN) {
+            exit_with("imgcat: --interactive takes files, not stdin");
+        }
+        if paths.is_empty() {
+            std::process::exit(1);
+        }
+        if let Err(e) = viewer::run(viewer, &paths, &opts) {
+            eprintln!("imgcat: {}", e);
+            failed = true;
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.flag_grid {
+        failed |= !grid::show_grid(viewer, &paths, &opts, args.flag_columns, false);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let player = Player {
+        loops: args.flag_loops,
+        speed: args.flag_speed,
+    };
+    let stop = if args.flag_animate {
+        Some(stop_on_ctrl_c().unwrap_or_else(|e| exit_with(&e.to_string())))
     } else {
-        read_img_from_stdio().unwrap()
+        None
+    };
+    for path in paths {
+        let shown = match &stop {
+            Some(stop) => animate(viewer, &path, &opts, &player, stop),
+            None => load_image(&path, &opts)
+                .and_then(|img| {
+                    match opts.cursor {
+                        Some(cursor) => viewer.display_at(&img, cursor),
+                        None => viewer.display(&img),
+                    }
+                    .map_err(|e| e.to_string())
+                }),
+        };
+        match shown {
+            Ok(_) if args.flag_caption => println!("{}", path),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("imgcat: {}: {}", path, e);
+                failed = true;
+            }
+        }
+        if stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::SeqCst))
+        {
+            break;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+fn animate(
+    viewer: &dyn Graphic,
+    path: &str,
+    opts: &DisplayOptions,
+    player: &Player,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    let data = read_file(path)?;
+    let animation = decode_frames(&data, opts).map_err(|e| e.to_string())?;
+    player
+        .play(viewer, &animation, stop)
+        .map_err(|e| e.to_string())
+}
+/// Writes what draws the images at `paths` to the file `output`, one after the other.
+///
+/// Returns whether every image could be written.
+fn write_output(viewer: &dyn Graphic, paths: &[String], output: &str, opts: &DisplayOptions) -> bool {
+    let mut out = match std::fs::File::create(output) {
+        Ok(file) => std::io::BufWriter::new(file),
+        Err(e) => exit_with(&format!("imgcat: {}: {}", output, e)),
     };
-    viewer.display(&img);
+    let mut failed = false;
+    for path in existing_paths(paths, &mut failed) {
+        let written = load_image(&path, opts)
+            .and_then(|img| viewer.encode(&img, &mut out).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("imgcat: {}: {}", path, e);
+            failed = true;
+        }
+    }
+    !failed
 }
-fn read_img_from_stdio() -> Result<DynamicImage, String> {
+/// Shows the images streamed on stdin one after the other at the same spot, skipping
+/// those that fail to decode, until the stream ends or the user presses Ctrl-C.
+fn show_stream(viewer: &dyn Graphic, opts: &DisplayOptions) -> Result<(), String> {
+    let stop = stop_on_ctrl_c().map_err(|e| e.to_string())?;
+    let mut canvas = Canvas::new(viewer);
+    for data in ImageStream::new(std::io::stdin().lock()) {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let data = data.map_err(|e| e.to_string())?;
+        if let Ok(img) = termimg::utils::prepare_img_from_bytes(&data, opts) {
+            canvas.draw(&img).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+/// Shows `paths` the way `ls` would: the files given first, then the images of each
+/// directory under a heading, all as thumbnails captioned with names and dimensions.
+fn show_listings(
+    viewer: &dyn Graphic,
+    paths: &[String],
+    opts: &DisplayOptions,
+    args: &Args,
+) -> bool {
+    let (dirs, files): (Vec<&String>, Vec<&String>) =
+        paths.iter().partition(|path| Path::new(path).is_dir());
+    let mut listings = vec![];
+    let mut ok = true;
+    for dir in dirs {
+        match listing::list_images(Path::new(dir), args.flag_recursive) {
+            Ok(mut found) => listings.append(&mut found),
+            Err(e) => {
+                eprintln!("imgcat: {}: {}", dir, e);
+                ok = false;
+            }
+        }
+    }
+    let has_files = !files.is_empty();
+    let headings = has_files || listings.len() > 1;
+    if has_files {
+        let files: Vec<String> = files.into_iter().cloned().collect();
+        ok &= grid::show_grid(viewer, &files, opts, args.flag_columns, true);
+    }
+    for (i, found) in listings.iter().enumerate() {
+        if headings {
+            if i > 0 || has_files {
+                println!();
+            }
+            println!("{}:", found.dir);
+        }
+        ok &= grid::show_grid(viewer, &found.images, opts, args.flag_columns, true);
+    }
+    ok
+}
+/// `paths` with every directory replaced by the images it holds.
+fn image_paths(paths: Vec<String>, recursive: bool, failed: &mut bool) -> Vec<String> {
+    let mut images = vec![];
+    for path in paths {
+        if !Path::new(&path).is_dir() {
+            images.push(path);
+            continue;
+        }
+        match listing::list_images(Path::new(&path), recursive) {
+            Ok(found) => images.extend(found.into_iter().flat_map(|found| found.images)),
+            Err(e) => {
+                eprintln!("imgcat: {}: {}", path, e);
+                *failed = true;
+            }
+        }
+    }
+    images
+}
+/// The paths `args` expand to, reporting and flagging those that match nothing.
+fn existing_paths(args: &[String], failed: &mut bool) -> Vec<String> {
+    let mut paths = vec![];
+    for path in expand_paths(args) {
+        match path {
+            Ok(path) => paths.push(path),
+            Err(e) => {
+                eprintln!("imgcat: {}", e);
+                *failed = true;
+            }
+        }
+    }
+    paths
+}
+/// Expands the glob patterns among `args`, for shells that pass them through unexpanded.
+fn expand_paths(args: &[String]) -> Vec<Result<String, String>> {
+    let mut paths = vec![];
+    for arg in args {
+        let is_pattern = arg.contains(['*', '?', '[']);
+        if !is_pattern || Path::new(arg).exists() {
+            paths.push(Ok(arg.clone()));
+            continue;
+        }
+        match glob::glob(arg) {
+            Ok(entries) => {
+                let before = paths.len();
+                for entry in entries {
+                    paths.push(
+                        entry
+                            .map(|p| p.to_string_lossy().into_owned())
+                            .map_err(|e| e.to_string()),
+                    );
+                }
+                if paths.len() == before {
+                    paths.push(Err(format!("{}: no matching files", arg)));
+                }
+            }
+            Err(e) => paths.push(Err(format!("{}: {}", arg, e))),
+        }
+    }
+    paths
+}
+/// Whether `dimension` is a percentage of the terminal.
+fn is_relative(dimension: Option<Dimension>) -> bool {
+    matches!(dimension, Some(Dimension::Percent(_)))
+}
+fn exit_with(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1)
+}
+/// The display options the flags ask for, the config file's `settings` filling in
+/// for those not given.
+fn display_options(args: &Args, settings: &Settings, size: Option<TerminalSize>) -> DisplayOptions {
+    let mut opts = DisplayOptions::new(size);
+    let or_setting = |flag: &Option<String>, setting: &Option<String>| {
+        flag.clone().or_else(|| setting.clone())
+    };
+    opts.background = parse(&or_setting(&args.flag_background, &settings.background));
+    opts.width = parse(&args.flag_width);
+    opts.height = parse(&args.flag_height);
+    opts.scale = args
+        .flag_scale
+        .as_ref()
+        .map(|scale| parse_scale(scale).unwrap_or_else(|e| exit_with(&e)));
+    if let Some(fit) = parse(&or_setting(&args.flag_fit, &settings.fit)) {
+        opts.fit = fit;
+    }
+    opts.upscale = !args.flag_no_upscale;
+    // Each image is shown once, so cropping before sending beats having kitty cut the
+    // region out of a full upload.
+    opts.crop = parse(&args.flag_crop);
+    opts.transforms = transforms(args);
+    opts.cursor = parse(&args.flag_cursor);
+    opts.max_width = parse(&settings.max_width);
+    opts.max_height = parse(&settings.max_height);
+    if let Some(filter) = &or_setting(&args.flag_filter, &settings.filter) {
+        opts.filter = parse_filter(filter)
+            .unwrap_or_else(|| exit_with(&format!("Invalid filter: {}", filter)));
+    }
+    opts
+}
+/// The transforms the flags ask for: orientation first, then zoom, so that the zoomed
+/// part is where it shows, then colours.
+fn transforms(args: &Args) -> Vec<Transform> {
+    let mut transforms = vec![];
+    if let Some(degrees) = args.flag_rotate {
+        transforms.push(Transform::Rotate(degrees));
+    }
+    if let Some(axis) = &args.flag_flip {
+        transforms.extend(transform::parse_flip(axis).unwrap_or_else(|e| exit_with(&e)));
+    }
+    if let Some(zoom) = &args.flag_zoom {
+        let factor = transform::parse_zoom(zoom).unwrap_or_else(|e| exit_with(&e));
+        let (x, y) = match &args.flag_pan {
+            Some(pan) => transform::parse_offset(pan).unwrap_or_else(|e| exit_with(&e)),
+            None => (0, 0),
+        };
+        transforms.push(Transform::Zoom { factor, x, y });
+    }
+    if args.flag_grayscale {
+        transforms.push(Transform::Grayscale);
+    }
+    if args.flag_invert {
+        transforms.push(Transform::Invert);
+    }
+    if let Some(value) = args.flag_brightness {
+        transforms.push(Transform::Brightness(value));
+    }
+    if let Some(percent) = args.flag_contrast {
+        transforms.push(Transform::Contrast(percent));
+    }
+    transforms
+}
+fn parse<T: FromStr<Err = String>>(value: &Option<String>) -> Option<T> {
+    value
+        .as_ref()
+        .map(|value| value.parse().unwrap_or_else(|e: String| exit_with(&e)))
+}
+/// The files given, or stdin when none is and it is not a terminal, or `--stdio` asks.
+fn input_files(args: &Args) -> Vec<String> {
+    if !args.arg_file.is_empty() {
+        return args.arg_file.clone();
+    }
+    if !args.flag_stdio && termion::is_tty(&std::io::stdin()) {
+        exit_with("imgcat: no image given, pass a file or pipe one in");
+    }
+    vec![STDIN.to_owned()]
+}
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    if path != STDIN {
+        return std::fs::read(path).map_err(|err| err.to_string());
+    }
     let mut buffer = vec![];
     std::io::stdin()
         .read_to_end(&mut buffer)
         .map_err(|err| err.to_string())?;
-    let buffer = buffer.as_bytes();
-    let reader = seek_bufread::BufReader::new(Cursor::new(&buffer));
-
-    // let buffer = buffer.as_bytes();
-    if let Some(kind) = infer::get(&buffer) {
-        if let Some(fmt) = get_image_format(&kind) {
-            let img = image::load(reader, fmt).map_err(|err| err.to_string())?;
-            Ok(img)
-        } else {
-            Err("Failed to decode Image".into())
-        }
-    } else {
-        Err("Unknow date format from stdin".into())
-    }
-}
-fn get_image_format(typ: &Type) -> Option<ImageFormat> {
-    match typ.mime_type() {
-        "image/jpeg" => Some(ImageFormat::Jpeg),
-        "image/png" => Some(ImageFormat::Png),
-        "image/gif" => Some(ImageFormat::Gif),
-        "image/webp" => Some(ImageFormat::WebP),
-        "image/tiff" => Some(ImageFormat::Tiff),
-        "image/bmp" => Some(ImageFormat::Bmp),
-        _ => None,
-    }
-}
-fn get_viewer(
-    viewers: &Vec<Box<dyn termimg::graphic::Graphic>>,
-    name: String,
-) -> Option<&Box<dyn termimg::graphic::Graphic>> {
-    if name == "auto" {
-        for v in viewers.iter() {
-            if v.supported() {
-                return Some(v);
-            }
-        }
+    Ok(buffer)
+}
+/// Loads the image at `path`, or from stdin for [`STDIN`], laid out as `opts` asks.
+fn load_image(path: &String, opts: &DisplayOptions) -> Result<DynamicImage, String> {
+    if path == STDIN {
+        termimg::utils::prepare_img_from_bytes(&read_file(path)?, opts)
     } else {
-        for v in viewers.iter() {
-            if v.name() == name {
-                return Some(v);
-            }
+        termimg::utils::prepare_img_with_options(path, opts)
+    }
+}
+/// The protocol asked for with `--protocol`, else in the environment, else in the
+/// config file's `settings`.
+fn preference(args: &Args, settings: &Settings) -> Preference {
+    let preference = match &args.flag_protocol {
+        Some(protocol) => Some(protocol.parse()),
+        None => Preference::from_env(),
+    };
+    let preference = preference
+        .or_else(|| settings.protocol.as_ref().map(|protocol| protocol.parse()))
+        .unwrap_or(Ok(Preference::Auto))
+        .unwrap_or_else(|e: String| exit_with(&e));
+    match (&preference, &settings.order) {
+        (Preference::Auto, Some(order)) if !order.is_empty() => Preference::Ordered(order.clone()),
+        _ => preference,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_paths_globs_and_keeps_going() {
+        let dir = std::env::temp_dir().join(format!("imgcat-expand-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.png", "b.png", "c.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
         }
+        let at = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let args = [at("*.png"), at("c.txt"), at("*.gif"), at("missing.jpg")];
+        let expanded = expand_paths(&args);
+        let mut failed = false;
+        let existing = existing_paths(&args, &mut failed);
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(expanded[..3], [Ok(at("a.png")), Ok(at("b.png")), Ok(at("c.txt"))]);
+        assert_eq!(expanded[3], Err(format!("{}: no matching files", at("*.gif"))));
+        // Paths without a pattern are left for loading to report.
+        assert_eq!(expanded[4], Ok(at("missing.jpg")));
+        assert_eq!(existing, [at("a.png"), at("b.png"), at("c.txt"), at("missing.jpg")]);
+        assert!(failed);
     }
-    None
 }
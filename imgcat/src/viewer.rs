@@ -0,0 +1,218 @@
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use signal_hook::consts::SIGWINCH;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::IntoAlternateScreen;
+use termion::{clear, cursor, style};
+use termimg::graphic::{guess_size, Graphic, TerminalSize};
+use termimg::options::DisplayOptions;
+use termimg::term::read_input;
+use termimg::utils::prepare_img_with_options;
+
+const ZOOM_STEP: f32 = 1.25;
+/// How far a pan moves, as a fraction of the visible part of the image.
+const PAN_STEP: f32 = 0.2;
+const HELP: &str =
+    "n/p: next/previous  +/-: zoom  0: reset  hjkl/arrows: pan  f: fit  r: rotate  i: info  q: quit";
+
+/// How the current image is looked at.
+struct View {
+    zoom: f32,
+    /// Centre of the visible part, as fractions of the image width and height.
+    center: (f32, f32),
+    /// Fit the image to the screen rather than showing it pixel for pixel.
+    fit: bool,
+    /// Clockwise quarter turns.
+    quarter_turns: u8,
+}
+
+impl View {
+    fn new(fit: bool) -> View {
+        View {
+            zoom: 1.0,
+            center: (0.5, 0.5),
+            fit,
+            quarter_turns: 0,
+        }
+    }
+
+    /// The part of `img` in view, scaled to fit a `width` x `height` pixels box.
+    fn render(&mut self, img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        let rotated = match self.quarter_turns % 4 {
+            1 => img.rotate90(),
+            2 => img.rotate180(),
+            3 => img.rotate270(),
+            _ => img.clone(),
+        };
+        let (w, h) = (rotated.width() as f32, rotated.height() as f32);
+        let base = if self.fit {
+            (width as f32 / w).min(height as f32 / h).min(1.0)
+        } else {
+            1.0
+        };
+        let scale = base * self.zoom;
+        let view_w = (width as f32 / scale).min(w);
+        let view_h = (height as f32 / scale).min(h);
+        // Keep the view inside the image, and remember where it ended up so that
+        // panning back from an edge takes effect at once.
+        let cx = (self.center.0 * w).clamp(view_w / 2.0, w - view_w / 2.0);
+        let cy = (self.center.1 * h).clamp(view_h / 2.0, h - view_h / 2.0);
+        self.center = (cx / w, cy / h);
+        let filter = if scale > 1.0 {
+            FilterType::Nearest
+        } else {
+            FilterType::Triangle
+        };
+        rotated
+            .crop_imm(
+                (cx - view_w / 2.0) as u32,
+                (cy - view_h / 2.0) as u32,
+                view_w.max(1.0) as u32,
+                view_h.max(1.0) as u32,
+            )
+            .resize_exact(
+                ((view_w * scale) as u32).max(1),
+                ((view_h * scale) as u32).max(1),
+                filter,
+            )
+    }
+
+    fn pan(&mut self, dx: f32, dy: f32) {
+        self.center.0 = (self.center.0 + dx * PAN_STEP / self.zoom).clamp(0.0, 1.0);
+        self.center.1 = (self.center.1 + dy * PAN_STEP / self.zoom).clamp(0.0, 1.0);
+    }
+}
+
+/// Browses the images at `paths` full screen until the user quits.
+pub fn run(viewer: &dyn Graphic, paths: &[String], opts: &DisplayOptions) -> std::io::Result<()> {
+    let resized = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGWINCH, Arc::clone(&resized))?;
+    let mut opts = opts.clone();
+    opts.size = None;
+
+    let mut screen = stdout().into_raw_mode()?.into_alternate_screen()?;
+    write!(screen, "{}", cursor::Hide)?;
+    let mut index = 0;
+    let mut current = prepare_img_with_options(&paths[index], &opts);
+    let mut view = View::new(true);
+    let mut info = false;
+    let mut dirty = true;
+    'main: loop {
+        if dirty || resized.swap(false, Ordering::SeqCst) {
+            let position = (index + 1, paths.len());
+            draw(&mut screen, viewer, &paths[index], position, &current, &mut view, info)?;
+            screen.flush()?;
+            dirty = false;
+        }
+        let input = read_input(Duration::from_millis(50));
+        for key in input.as_slice().keys() {
+            let previous = index;
+            match key? {
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => break 'main,
+                Key::Char('n') | Key::Char(' ') | Key::PageDown => index = (index + 1) % paths.len(),
+                Key::Char('p') | Key::Backspace | Key::PageUp => {
+                    index = (index + paths.len() - 1) % paths.len()
+                }
+                Key::Char('+') | Key::Char('=') => view.zoom *= ZOOM_STEP,
+                Key::Char('-') => view.zoom = (view.zoom / ZOOM_STEP).max(1.0 / 64.0),
+                Key::Char('0') => view = View::new(view.fit),
+                Key::Char('h') | Key::Left => view.pan(-1.0, 0.0),
+                Key::Char('l') | Key::Right => view.pan(1.0, 0.0),
+                Key::Char('k') | Key::Up => view.pan(0.0, -1.0),
+                Key::Char('j') | Key::Down => view.pan(0.0, 1.0),
+                Key::Char('f') => view.fit = !view.fit,
+                Key::Char('r') => view.quarter_turns = (view.quarter_turns + 1) % 4,
+                Key::Char('R') => view.quarter_turns = (view.quarter_turns + 3) % 4,
+                Key::Char('i') => info = !info,
+                _ => continue,
+            }
+            if index != previous {
+                current = prepare_img_with_options(&paths[index], &opts);
+                view = View::new(view.fit);
+            }
+            dirty = true;
+        }
+    }
+    let _ = viewer.clear();
+    write!(screen, "{}{}", clear::All, cursor::Show)?;
+    screen.flush()
+}
+
+fn draw<W: Write>(
+    screen: &mut W,
+    viewer: &dyn Graphic,
+    path: &str,
+    (nth, total): (usize, usize),
+    img: &Result<DynamicImage, String>,
+    view: &mut View,
+    info: bool,
+) -> std::io::Result<()> {
+    let size = viewer.size().or_else(guess_size).unwrap_or(TerminalSize {
+        width: 640,
+        height: 384,
+        cols: 80,
+        rows: 24,
+    });
+    write!(screen, "{}{}", clear::All, cursor::Goto(1, 1))?;
+    screen.flush()?;
+    let _ = viewer.clear();
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned());
+    let mut status = format!("{}  [{}/{}]", name, nth, total);
+    match img {
+        Ok(img) => {
+            // The last row is for the status line.
+            let (_, cell_height) = size.cell_size();
+            let height = (size.height as f32 - cell_height).max(1.0) as u32;
+            let frame = view.render(img, size.width as u32, height);
+            if let Err(e) = viewer.display_in_place(&frame) {
+                write!(screen, "{}", e)?;
+            }
+            status += &format!(
+                "  {}x{}  {:.0}%{}",
+                img.width(),
+                img.height(),
+                view.zoom * 100.0,
+                if view.fit { "  fit" } else { "" }
+            );
+            if info {
+                let lines = [
+                    path.to_owned(),
+                    format!("{}x{} {:?}", img.width(), img.height(), img.color()),
+                    format!("rotation {}\u{b0}", view.quarter_turns as u16 * 90),
+                    HELP.to_owned(),
+                ];
+                for (row, line) in lines.iter().enumerate() {
+                    write!(
+                        screen,
+                        "{}{}{}{}",
+                        cursor::Goto(1, row as u16 + 1),
+                        style::Invert,
+                        line,
+                        style::Reset
+                    )?;
+                }
+            }
+        }
+        Err(e) => write!(screen, "{}: {}", path, e)?,
+    }
+    let status: String = status.chars().take(size.cols as usize).collect();
+    write!(
+        screen,
+        "{}{}{}{}",
+        cursor::Goto(1, size.rows),
+        style::Invert,
+        status,
+        style::Reset
+    )
+}
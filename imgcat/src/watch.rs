@@ -0,0 +1,78 @@
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use termimg::animation::stop_on_ctrl_c;
+use termimg::graphic::{Canvas, Graphic};
+use termimg::options::DisplayOptions;
+use termimg::utils::prepare_img_with_options;
+
+/// How often the file is looked at for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What tells one version of the file from the next.
+type Stamp = (SystemTime, u64);
+
+fn stamp(path: &String) -> std::io::Result<Stamp> {
+    let meta = fs::metadata(path)?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+/// Whether the file at `path` differs from the version `seen` last, which it then
+/// becomes. The file may be missing for a moment while it is replaced: that is no
+/// change.
+fn changed(path: &String, seen: &mut Option<Stamp>) -> bool {
+    match stamp(path) {
+        Ok(current) if *seen != Some(current) => {
+            *seen = Some(current);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Shows the image at `path`, then redraws it in place each time the file changes,
+/// until the user presses Ctrl-C.
+///
+/// A version that fails to decode is taken to be still being written: the image
+/// shown stays up until the file changes again.
+pub fn watch(viewer: &dyn Graphic, path: &String, opts: &DisplayOptions) -> Result<(), String> {
+    stamp(path).map_err(|e| e.to_string())?;
+    let stop = stop_on_ctrl_c().map_err(|e| e.to_string())?;
+    let mut canvas = Canvas::new(viewer);
+    let mut seen = None;
+    while !stop.load(Ordering::SeqCst) {
+        if changed(path, &mut seen) {
+            if let Ok(img) = prepare_img_with_options(path, opts) {
+                canvas.draw(&img).map_err(|e| e.to_string())?;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_told_by_the_stamp() {
+        let path = std::env::temp_dir()
+            .join(format!("imgcat-watch-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut seen = None;
+        assert!(!changed(&path, &mut seen));
+        fs::write(&path, b"one").unwrap();
+        let first = changed(&path, &mut seen);
+        let again = changed(&path, &mut seen);
+        // Same modification time, maybe, but another length.
+        fs::write(&path, b"three").unwrap();
+        let rewritten = changed(&path, &mut seen);
+        fs::remove_file(&path).unwrap();
+        let removed = changed(&path, &mut seen);
+        assert_eq!([first, again, rewritten, removed], [true, false, true, false]);
+    }
+}
@@ -0,0 +1,62 @@
+//! What the integration tests share: temporary directories and running imgcat on a
+//! terminal.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A directory of its own for a test, removed when dropped, failed asserts included.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("imgcat-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /// Writes `config` as the config file imgcat reads when run by [`imgcat_on_tty`].
+    pub fn write_config(&self, config: &str) {
+        std::fs::create_dir_all(self.join("termimg")).unwrap();
+        std::fs::write(self.join("termimg").join("config.toml"), config).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `imgcat <args>` in a shell on an 80x24 pseudo-terminal, through util-linux
+/// `script`, with the config in `dir` and no multiplexer or terminal set in the
+/// environment, so that imgcat asks the terminal who it is. `args` may redirect stdin.
+/// What the terminal got comes back on stdout.
+pub fn imgcat_on_tty(dir: &TempDir, args: &str) -> Output {
+    let command = format!(
+        "stty cols 80 rows 24; {} {}",
+        env!("CARGO_BIN_EXE_imgcat"),
+        args
+    );
+    Command::new("script")
+        .args(["-qec", &command, "/dev/null"])
+        .env_remove("TMUX")
+        .env_remove("STY")
+        .env_remove("KITTY_WINDOW_ID")
+        .env_remove("WEZTERM_EXECUTABLE")
+        .env_remove("KONSOLE_VERSION")
+        .env_remove("TERM_PROGRAM")
+        .env_remove("VTE_VERSION")
+        .env_remove("TERMIMG_PROTOCOL")
+        .env("XDG_CONFIG_HOME", dir.path())
+        .output()
+        .expect("util-linux `script` is needed to run imgcat on a terminal")
+}
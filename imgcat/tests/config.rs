@@ -0,0 +1,30 @@
+mod common;
+
+use std::process::Command;
+
+use common::TempDir;
+
+/// Runs imgcat with `config` as its config file and returns what it printed on stderr
+/// and whether it succeeded.
+fn run_with_config(name: &str, config: &str) -> (String, bool) {
+    let dir = TempDir::new(&format!("config-{}", name));
+    dir.write_config(config);
+    let output = Command::new(env!("CARGO_BIN_EXE_imgcat"))
+        .args(["--protocol", "mosaic", "--list-protocol"])
+        .env("XDG_CONFIG_HOME", dir.path())
+        .output()
+        .unwrap();
+    (String::from_utf8_lossy(&output.stderr).into_owned(), output.status.success())
+}
+
+#[test]
+fn unknown_settings_are_rejected() {
+    let (stderr, ok) = run_with_config("top", "bakground = \"#000000\"\n");
+    assert!(!ok);
+    assert!(stderr.contains("`bakground`"), "{}", stderr);
+    let (stderr, ok) = run_with_config("terminal", "[terminal.kitty]\nprotocl = \"sixel\"\n");
+    assert!(!ok);
+    assert!(stderr.contains("`protocl`"), "{}", stderr);
+    let (stderr, ok) = run_with_config("known", "background = \"#000000\"\n");
+    assert!(ok, "{}", stderr);
+}
@@ -0,0 +1,33 @@
+extern crate image;
+
+mod common;
+
+use common::{imgcat_on_tty, TempDir};
+
+/// A directory holding an image and a gzip file that is no SVG lists the image only,
+/// with the size of the file.
+#[test]
+fn listing_skips_files_that_do_not_decode() {
+    let dir = TempDir::new("listing");
+    let images = dir.join("images");
+    std::fs::create_dir_all(&images).unwrap();
+    image::RgbImage::from_pixel(6, 4, image::Rgb([0, 0, 255]))
+        .save(images.join("a.png"))
+        .unwrap();
+    // A gzip header followed by a deflated "hello".
+    std::fs::write(
+        images.join("notes.gz"),
+        b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xcbH\xcd\xc9\xc9\x07\x00\x86\xa6\x106\x05\x00\x00\x00",
+    )
+    .unwrap();
+    // Listings are laid out on a terminal.
+    // Rotated for display, the image is still listed with its own size.
+    let output = imgcat_on_tty(
+        &dir,
+        &format!("--protocol mosaic --rotate 90 {} < /dev/null", images.display()),
+    );
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", text);
+    assert!(text.contains("a.png 6x4"), "{}", text);
+    assert!(!text.contains("notes.gz"), "{}", text);
+}
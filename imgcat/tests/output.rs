@@ -0,0 +1,69 @@
+extern crate image;
+
+mod common;
+
+use common::{imgcat_on_tty, TempDir};
+
+/// Writes a transparent image to a file with the background set to the terminal's in
+/// the config, and checks that the terminal is not asked for its colour.
+#[test]
+fn output_file_does_not_query_the_terminal() {
+    let dir = TempDir::new("output");
+    dir.write_config("background = \"terminal\"\n");
+    let png = dir.join("clear.png");
+    image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 128]))
+        .save(&png)
+        .unwrap();
+    let out = dir.join("clear.txt");
+    let output = imgcat_on_tty(
+        &dir,
+        &format!("--protocol mosaic --output {} {} < /dev/null", out.display(), png.display()),
+    );
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", text);
+    assert!(!text.contains("\x1b]11;?"), "{:?}", text);
+    assert!(!std::fs::read(&out).unwrap().is_empty());
+}
+
+/// Settings for some terminal in the config do not make imgcat ask this one who it is.
+#[test]
+fn output_file_does_not_identify_the_terminal() {
+    let dir = TempDir::new("output-terminal");
+    dir.write_config("[terminal.foot]\nprotocol = \"sixel\"\n");
+    let png = dir.join("red.png");
+    image::RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0]))
+        .save(&png)
+        .unwrap();
+    let out = dir.join("red.txt");
+    let output = imgcat_on_tty(
+        &dir,
+        &format!("--protocol mosaic --output {} {}", out.display(), png.display()),
+    );
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", text);
+    assert!(!text.contains("\x1b[>0q") && !text.contains("\x1b[>c"), "{:?}", text);
+}
+
+/// Without a terminal to take them of, percentages are refused rather than dropped.
+#[test]
+fn output_file_refuses_percentages() {
+    let dir = TempDir::new("output-percent");
+    let png = dir.join("red.png");
+    image::RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0]))
+        .save(&png)
+        .unwrap();
+    let out = dir.join("red.txt");
+    let output = imgcat_on_tty(
+        &dir,
+        &format!("--protocol sixel --width 50% --output {} {} < /dev/null", out.display(), png.display()),
+    );
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", text);
+    assert!(text.contains("not %"), "{}", text);
+    let output = imgcat_on_tty(
+        &dir,
+        &format!("--protocol sixel --width 4px --output {} {} < /dev/null", out.display(), png.display()),
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(std::fs::read(&out).unwrap().starts_with(b"\x1bP"));
+}
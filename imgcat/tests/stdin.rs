@@ -0,0 +1,48 @@
+extern crate image;
+
+mod common;
+
+use common::{imgcat_on_tty, TempDir};
+
+/// Runs imgcat on a terminal with a PNG piped to its stdin, so that the terminal is
+/// probed while the image waits on stdin.
+#[test]
+fn piped_image_survives_terminal_probes() {
+    let dir = TempDir::new("stdin");
+    let png = dir.join("piped.png");
+    image::RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0]))
+        .save(&png)
+        .unwrap();
+    let output = imgcat_on_tty(&dir, &format!("--background terminal - < {}", png.display()));
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(!text.contains("Unknown image format"), "{}", text);
+    assert!(output.status.success(), "{}", text);
+}
+
+/// An image piped to stdin does not keep imgcat from asking the terminal who it is.
+#[test]
+fn piped_image_does_not_stop_identification() {
+    let dir = TempDir::new("stdin-identify");
+    let png = dir.join("piped.png");
+    image::RgbImage::from_pixel(4, 4, image::Rgb([0, 255, 0]))
+        .save(&png)
+        .unwrap();
+    let output = imgcat_on_tty(&dir, &format!("--list-protocol < {}", png.display()));
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", text);
+    assert!(text.contains("\x1b[>0q\x1b[>c"), "{:?}", text);
+}
+
+/// The viewer loads images again as it moves between them, which stdin cannot be.
+#[test]
+fn interactive_refuses_stdin() {
+    let dir = TempDir::new("stdin-interactive");
+    let png = dir.join("piped.png");
+    image::RgbImage::from_pixel(4, 4, image::Rgb([0, 0, 255]))
+        .save(&png)
+        .unwrap();
+    let output = imgcat_on_tty(&dir, &format!("--interactive - < {}", png.display()));
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", text);
+    assert!(text.contains("--interactive takes files, not stdin"), "{}", text);
+}
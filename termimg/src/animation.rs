@@ -0,0 +1,246 @@
+//! Playing animated GIF, PNG (APNG) and WebP images on any [`Graphic`] backend.
+extern crate signal_hook;
+extern crate termion;
+use std::io::{stdout, Cursor, ErrorKind, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use self::termion::cursor::{Down, Hide, Show, Up};
+use image::{AnimationDecoder, DynamicImage, Frames, ImageFormat};
+
+use crate::graphic::{new_image_id, rows_spanned, DisplayResult, Graphic};
+use crate::options::DisplayOptions;
+use crate::transform;
+use crate::utils::{convert_to_rgb_rgba, crop_image, fit_image, get_image_from_bytes_with_options};
+use crate::utils::{sniff_format, ImageKind, LoadImageError};
+
+/// Frames asking for a shorter delay than this are shown for [`DEFAULT_DELAY`], as
+/// browsers do: such GIFs rely on it.
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// The frames of an animated image and how many times it asks to be played.
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    /// How many times the file asks for the animation to be played, 0 for ever.
+    pub loops: u32,
+}
+
+pub struct Frame {
+    pub image: DynamicImage,
+    /// How long the frame stays on screen.
+    pub delay: Duration,
+}
+
+/// Decodes every frame of an animated image. Still images give a single frame.
+pub fn decode_frames(data: &[u8], opts: &DisplayOptions) -> Result<Animation, LoadImageError> {
+    let frames = match sniff_format(data) {
+        #[cfg(feature = "gif")]
+        Some(ImageKind::Raster(ImageFormat::Gif)) => {
+            Some(image::codecs::gif::GifDecoder::new(Cursor::new(data))?.into_frames())
+        }
+        #[cfg(feature = "png")]
+        Some(ImageKind::Raster(ImageFormat::Png)) => {
+            let decoder = image::codecs::png::PngDecoder::new(Cursor::new(data))?;
+            if decoder.is_apng() {
+                Some(decoder.apng().into_frames())
+            } else {
+                None
+            }
+        }
+        #[cfg(feature = "webp")]
+        Some(ImageKind::Raster(ImageFormat::WebP)) => {
+            let decoder = image::codecs::webp::WebPDecoder::new(Cursor::new(data))?;
+            if decoder.has_animation() {
+                Some(decoder.into_frames())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let frames = match frames {
+        Some(frames) => collect(frames, opts)?,
+        None => vec![Frame {
+            image: prepare_frame(
+                convert_to_rgb_rgba(get_image_from_bytes_with_options(data, opts)?),
+                opts,
+            )?,
+            delay: DEFAULT_DELAY,
+        }],
+    };
+    Ok(Animation {
+        frames,
+        loops: loop_count(data),
+    })
+}
+
+/// How many times the animated GIF, PNG or WebP image in `data` asks to be played, 0
+/// for ever. GIFs count repetitions after the first play, and play once without the
+/// NETSCAPE2.0 extension, as in browsers.
+pub fn loop_count(data: &[u8]) -> u32 {
+    let u32_be = |at: usize| data.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let u32_le = |at: usize| data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let u16_le = |at: usize| data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32);
+    if data.starts_with(b"GIF8") {
+        let netscape = b"\x21\xff\x0bNETSCAPE2.0\x03\x01";
+        return match data.windows(netscape.len()).position(|w| w == netscape) {
+            Some(at) => match u16_le(at + netscape.len()) {
+                Some(0) => 0,
+                Some(repeats) => repeats + 1,
+                None => 1,
+            },
+            None => 1,
+        };
+    }
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // acTL comes before the first IDAT.
+        let mut at = 8;
+        while let Some(len) = u32_be(at) {
+            match data.get(at + 4..at + 8) {
+                Some(b"acTL") => return u32_be(at + 12).unwrap_or(0),
+                Some(b"IDAT") | None => break,
+                _ => at += 12 + len as usize,
+            }
+        }
+        return 0;
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        let mut at = 12;
+        while let Some(len) = u32_le(at + 4) {
+            match data.get(at..at + 4) {
+                Some(b"ANIM") => return u16_le(at + 12).unwrap_or(0),
+                _ => at += 8 + (len as usize).next_multiple_of(2),
+            }
+        }
+    }
+    0
+}
+
+fn collect(frames: Frames, opts: &DisplayOptions) -> Result<Vec<Frame>, LoadImageError> {
+    frames
+        .map(|frame| {
+            let frame = frame?;
+            let delay = Duration::from(frame.delay());
+            Ok(Frame {
+                image: prepare_frame(DynamicImage::ImageRgba8(frame.into_buffer()), opts)?,
+                delay: if delay < MIN_DELAY { DEFAULT_DELAY } else { delay },
+            })
+        })
+        .collect()
+}
+
+fn prepare_frame(img: DynamicImage, opts: &DisplayOptions) -> Result<DynamicImage, LoadImageError> {
+    crop_image(img, opts)
+        .map(|img| transform::apply(img, &opts.transforms))
+        .map(|img| fit_image(img, opts))
+        .map_err(|e| LoadImageError::IoError(std::io::Error::new(ErrorKind::InvalidInput, e)))
+}
+
+/// Returns a flag raised when the user presses Ctrl-C, for [`Player::play`] to stop on.
+pub fn stop_on_ctrl_c() -> std::io::Result<Arc<AtomicBool>> {
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+    Ok(stop)
+}
+
+/// Shows frames one after the other at the same spot, at the pace they ask for.
+pub struct Player {
+    /// How many times the animation is played, 0 for ever, or `None` for as many as
+    /// the file asks.
+    pub loops: Option<u32>,
+    /// How much faster than asked frames are shown, 2.0 plays twice as fast.
+    pub speed: f32,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Player {
+            loops: None,
+            speed: 1.0,
+        }
+    }
+}
+
+impl Player {
+    /// Plays `animation` below the cursor until done or `stop` is raised, then leaves
+    /// the cursor on the line below the animation.
+    pub fn play(&self, viewer: &dyn Graphic, animation: &Animation, stop: &AtomicBool) -> DisplayResult {
+        let frames = &animation.frames;
+        let loops = self.loops.unwrap_or(animation.loops);
+        let height = frames.iter().map(|f| f.image.height()).max().unwrap_or(0);
+        let rows = rows_spanned(viewer, height);
+        let mut stdout = stdout();
+        // Make room first, so that drawing never scrolls the frames apart.
+        write!(stdout, "{}{}{}", Hide, "\n".repeat(rows as usize), Up(rows))?;
+        stdout.flush()?;
+        let id = new_image_id();
+        let r = self.play_frames(viewer, frames, loops, stop, id);
+        write!(stdout, "\r{}{}", Down(rows), Show)?;
+        stdout.flush()?;
+        r
+    }
+
+    fn play_frames(
+        &self,
+        viewer: &dyn Graphic,
+        frames: &[Frame],
+        loops: u32,
+        stop: &AtomicBool,
+        id: u32,
+    ) -> DisplayResult {
+        let mut played = 0;
+        while loops == 0 || played < loops {
+            for frame in frames {
+                let shown_at = Instant::now();
+                viewer.redraw_in_place(&frame.image, id)?;
+                let delay = frame.delay.div_f32(self.speed.max(0.01));
+                while shown_at.elapsed() < delay {
+                    if stop.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                    thread::sleep((delay - shown_at.elapsed().min(delay)).min(MIN_DELAY));
+                }
+            }
+            if frames.len() <= 1 {
+                break;
+            }
+            played += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn loop_count_from_the_file() {
+        let gif = |extension: &[u8]| [b"GIF89a\x01\x00\x01\x00\x00\x00\x00", extension, b";"].concat();
+        assert_eq!(loop_count(&gif(b"")), 1);
+        assert_eq!(loop_count(&gif(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x02\x00\x00")), 3);
+        assert_eq!(loop_count(&gif(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")), 0);
+
+        let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+        apng.extend(png_chunk(b"IHDR", &[0; 13]));
+        apng.extend(png_chunk(b"acTL", &[0, 0, 0, 2, 0, 0, 0, 4]));
+        apng.extend(png_chunk(b"IDAT", &[]));
+        assert_eq!(loop_count(&apng), 4);
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+        webp.extend_from_slice(&[0; 10]);
+        webp.extend_from_slice(b"ANIM\x06\0\0\0\0\0\0\0\x05\0");
+        assert_eq!(loop_count(&webp), 5);
+    }
+}
@@ -1,19 +1,21 @@
 extern crate base64;
 extern crate termion;
-use self::termion::async_stdin;
 use self::termion::raw::IntoRawMode;
 use std::{
     collections::HashMap,
-    io::{stdout, Read, Write},
-    thread,
+    io::{stdout, Write},
     time::Duration,
 };
 
+use crate::term::{passthrough, read_input};
+
 use self::base64::Engine;
 
 pub enum ControlValue {
     Str(String),
     U16(u16),
+    U32(u32),
+    I32(i32),
 }
 pub struct APC {
     contro_data: HashMap<String, ControlValue>,
@@ -54,6 +56,12 @@ impl APC {
                 ControlValue::U16(v) => {
                     let _ = data.write(v.to_string().as_bytes());
                 }
+                ControlValue::U32(v) => {
+                    let _ = data.write(v.to_string().as_bytes());
+                }
+                ControlValue::I32(v) => {
+                    let _ = data.write(v.to_string().as_bytes());
+                }
             }
             data.push(b',');
         }
@@ -69,30 +77,28 @@ impl APC {
         data
     }
     pub fn write(self: &Self) -> String {
-        let mut stdin = async_stdin().bytes();
-        let mut stdout = stdout().lock().into_raw_mode().unwrap();
-        stdout.write(self.get().as_slice()).unwrap();
-        stdout.flush().unwrap();
-        thread::sleep(Duration::from_millis(100));
-        let mut buf = vec![];
-        while let Some(Ok(v)) = stdin.next() {
-            buf.push(v)
-        }
-        String::from_utf8(buf).map_or("".into(), |i| i)
+        write(&vec![self])
     }
 }
 
 pub fn write(apcs: &Vec<&APC>) -> String {
-    let mut stdin = async_stdin().bytes();
-    let mut stdout = stdout().lock().into_raw_mode().unwrap();
+    let mut stdout = match stdout().lock().into_raw_mode() {
+        Ok(stdout) => stdout,
+        Err(_) => return String::new(),
+    };
     for apc in apcs {
-        let _ = stdout.write(apc.get().as_slice());
+        let _ = stdout.write(passthrough(&apc.get()).as_slice());
     }
     let _ = stdout.flush();
-    thread::sleep(Duration::from_millis(100));
-    let mut buf = vec![];
-    while let Some(Ok(v)) = stdin.next() {
-        buf.push(v)
-    }
+    let buf = read_input(Duration::from_millis(100));
     String::from_utf8(buf).map_or("".into(), |i| i)
 }
+
+/// Writes `apcs` without waiting for the terminal to reply.
+pub fn send(apcs: &Vec<&APC>) {
+    let mut stdout = stdout().lock();
+    for apc in apcs {
+        let _ = stdout.write(passthrough(&apc.get()).as_slice());
+    }
+    let _ = stdout.flush();
+}
@@ -1,7 +1,13 @@
 use self::termion::terminal_size;
 use self::termion::terminal_size_pixels;
-use image::DynamicImage;
+use self::termion::cursor::{Down, Hide, Restore, Right, Save, Show, Up};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
 use std::error::Error;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::options::{CursorPolicy, Region};
 extern crate termion;
 #[derive(Debug, Clone)]
 pub struct TerminalSize {
@@ -11,6 +17,165 @@ pub struct TerminalSize {
     pub rows: u16,
 }
 
+impl TerminalSize {
+    /// Cell size in pixels assumed when the terminal does not report its pixel size.
+    pub const DEFAULT_CELL_SIZE: (u16, u16) = (8, 16);
+
+    /// The size of one cell in pixels.
+    pub fn cell_size(&self) -> (f32, f32) {
+        (
+            self.width as f32 / self.cols.max(1) as f32,
+            self.height as f32 / self.rows.max(1) as f32,
+        )
+    }
+}
+
+/// The terminal size, guessing the pixel size from [`TerminalSize::DEFAULT_CELL_SIZE`]
+/// when the terminal only reports rows and columns.
+pub fn guess_size() -> Option<TerminalSize> {
+    let (cols, rows) = terminal_size().ok()?;
+    let (width, height) = terminal_size_pixels()
+        .ok()
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .unwrap_or((
+            cols * TerminalSize::DEFAULT_CELL_SIZE.0,
+            rows * TerminalSize::DEFAULT_CELL_SIZE.1,
+        ));
+    Some(TerminalSize {
+        width,
+        height,
+        cols,
+        rows,
+    })
+}
+
+/// How many rows of text an image `height` pixels tall covers on `viewer`, at least one.
+pub fn rows_spanned<G: Graphic + ?Sized>(viewer: &G, height: u32) -> u16 {
+    match viewer.size().or_else(guess_size) {
+        Some(size) => (height as f32 / size.cell_size().1).ceil() as u16,
+        None => 1,
+    }
+    .max(1)
+}
+
+/// How many columns of text an image `width` pixels wide covers on `viewer`, at least one.
+pub fn cols_spanned<G: Graphic + ?Sized>(viewer: &G, width: u32) -> u16 {
+    match viewer.size().or_else(guess_size) {
+        Some(size) => (width as f32 / size.cell_size().0).ceil() as u16,
+        None => 1,
+    }
+    .max(1)
+}
+
+/// What makes room for `rows` rows from the cursor down and then puts the cursor back.
+/// IND moves down a line as a newline does, scrolling at the bottom of the screen, but
+/// stays in the same column.
+pub(crate) fn make_room(rows: u16) -> String {
+    if rows > 1 {
+        format!("{}{}", "\x1bD".repeat(rows as usize - 1), Up(rows - 1))
+    } else {
+        String::new()
+    }
+}
+
+/// What moves the cursor from the top left corner of an image of `cols` x `rows` cells
+/// to where `cursor` says.
+fn cursor_moves(cursor: CursorPolicy, cols: u16, rows: u16) -> String {
+    match cursor {
+        CursorPolicy::Start => String::new(),
+        CursorPolicy::After => Right(cols).to_string(),
+        CursorPolicy::Below if rows > 1 => format!("{}\n", Down(rows - 1)),
+        CursorPolicy::Below => "\n".to_owned(),
+    }
+}
+
+/// The pixel size of a box of `cols` x `rows` cells on `viewer`.
+pub fn cell_box_size<G: Graphic + ?Sized>(viewer: &G, cols: u16, rows: u16) -> (u32, u32) {
+    let (cell_w, cell_h) = viewer
+        .size()
+        .or_else(guess_size)
+        .map(|size| size.cell_size())
+        .unwrap_or((
+            TerminalSize::DEFAULT_CELL_SIZE.0 as f32,
+            TerminalSize::DEFAULT_CELL_SIZE.1 as f32,
+        ));
+    (
+        ((cols as f32 * cell_w) as u32).max(1),
+        ((rows as f32 * cell_h) as u32).max(1),
+    )
+}
+
+/// `img` scaled to fit a box of `cols` x `rows` cells on `viewer`, keeping its aspect
+/// ratio, and centred on a transparent image of exactly the size of the box.
+pub fn fit_to_cells<G: Graphic + ?Sized>(
+    viewer: &G,
+    img: &DynamicImage,
+    cols: u16,
+    rows: u16,
+) -> DynamicImage {
+    let (width, height) = cell_box_size(viewer, cols, rows);
+    let scaled = img.resize(width, height, FilterType::Triangle).to_rgba8();
+    let mut boxed = RgbaImage::new(width, height);
+    let x = (width - scaled.width()) / 2;
+    let y = (height - scaled.height()) / 2;
+    imageops::overlay(&mut boxed, &scaled, x as i64, y as i64);
+    DynamicImage::ImageRgba8(boxed)
+}
+
+/// Hands out a new id for an image to be shown, so that showing an image does not
+/// replace an earlier one. Ids start from the process id to keep clear of other
+/// programs drawing in the same window.
+pub fn new_image_id() -> u32 {
+    static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+    let _ = NEXT_ID.compare_exchange(
+        0,
+        ((std::process::id() & 0xffff) << 12) | 1,
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    );
+    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// The spot below the cursor where a series of images, such as the versions of a
+/// watched file, replace one another. The cursor is hidden meanwhile and goes to the
+/// line below the last image when the canvas is dropped.
+pub struct Canvas<'a> {
+    viewer: &'a dyn Graphic,
+    id: u32,
+    rows: u16,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(viewer: &'a dyn Graphic) -> Canvas<'a> {
+        print!("{}", Hide);
+        let _ = stdout().flush();
+        Canvas {
+            viewer,
+            id: new_image_id(),
+            rows: 0,
+        }
+    }
+
+    /// Replaces the image drawn last with `img`.
+    pub fn draw(&mut self, img: &DynamicImage) -> DisplayResult {
+        // Clear what the last image left, then make room for this one.
+        let rows = rows_spanned(self.viewer, img.height());
+        print!("\r\x1b[J{}{}", "\n".repeat(rows as usize), Up(rows));
+        stdout().flush()?;
+        self.rows = rows;
+        self.viewer.redraw_in_place(img, self.id)
+    }
+}
+
+impl Drop for Canvas<'_> {
+    fn drop(&mut self) {
+        if self.rows > 0 {
+            print!("\r{}", Down(self.rows));
+        }
+        print!("{}", Show);
+        let _ = stdout().flush();
+    }
+}
 
 pub type DisplayResult = Result<(), Box<dyn Error>>;
 
@@ -31,5 +196,103 @@ pub trait Graphic {
         }
     }
     fn display(&self, img: &DynamicImage) -> DisplayResult;
+    /// Writes what draws `img` to `out` rather than to the terminal, without querying
+    /// the terminal, for the bytes to be replayed later, with `cat` say. Images still
+    /// transparent are flattened against black by protocols that have no alpha.
+    ///
+    /// Fails by default, for protocols that can only draw on a live terminal.
+    fn encode(&self, img: &DynamicImage, out: &mut dyn Write) -> DisplayResult {
+        let _ = (img, out);
+        Err(format!("{} cannot write to a file", self.name()).into())
+    }
+    /// Draws `img` with its top left corner at the cursor and leaves the cursor there.
+    ///
+    /// The caller makes room below the cursor first: a terminal scrolling to fit the
+    /// image would move it away from where the cursor is restored.
+    fn display_in_place(&self, img: &DynamicImage) -> DisplayResult {
+        print!("{}", Save);
+        let _ = stdout().flush();
+        let r = self.display(img);
+        print!("{}", Restore);
+        let _ = stdout().flush();
+        r
+    }
+    /// Draws `img` with its top left corner at the cursor and leaves the cursor where
+    /// `cursor` says, whatever the protocol would do on its own.
+    ///
+    /// Backends need not handle the policies themselves: every protocol draws from the
+    /// cursor, and [`Graphic::display_in_place`] brings it back to the corner wherever
+    /// the protocol leaves it, kitty's included, from where the policy is a plain
+    /// cursor movement. Kitty's `C=1` would only spare the save and restore.
+    fn display_at(&self, img: &DynamicImage, cursor: CursorPolicy) -> DisplayResult {
+        let rows = rows_spanned(self, img.height());
+        let cols = cols_spanned(self, img.width());
+        let mut out = stdout();
+        // Make room first, lest the terminal scrolls the image away from the corner.
+        write!(out, "{}", make_room(rows))?;
+        out.flush()?;
+        self.display_in_place(img)?;
+        write!(out, "{}", cursor_moves(cursor, cols, rows))?;
+        out.flush()?;
+        Ok(())
+    }
+    /// Shows the `region` of `img`, as [`Graphic::display`] would show an image of that
+    /// size. Protocols that keep images cut the region out terminal side and, shown
+    /// again with the same `id` (from [`new_image_id`]), send only the new region, so
+    /// that a sprite sheet or a map is uploaded once; the others crop before encoding.
+    fn display_region(&self, img: &DynamicImage, region: Region, id: u32) -> DisplayResult {
+        let _ = id;
+        match region.within(img.width(), img.height()) {
+            Some(r) => self.display(&img.crop_imm(r.x, r.y, r.width, r.height)),
+            None => Ok(()),
+        }
+    }
+    /// Draws `img` into a box of exactly `cols` x `rows` cells at the cursor, keeping
+    /// its aspect ratio, and moves the cursor `cols` columns right, as a run of text
+    /// would. Room is made for the box first, scrolling the screen up at the bottom as
+    /// [`Graphic::display_at`] does, so that drawing does not scroll it away.
+    fn display_inline(&self, img: &DynamicImage, cols: u16, rows: u16) -> DisplayResult {
+        print!("{}", make_room(rows));
+        stdout().flush()?;
+        self.display_in_place(&fit_to_cells(self, img, cols, rows))?;
+        print!("{}", cursor_moves(CursorPolicy::After, cols, rows));
+        stdout().flush()?;
+        Ok(())
+    }
+    /// Like [`Graphic::display_in_place`], but replaces the image drawn before with the
+    /// same `id` (from [`new_image_id`]) rather than piling up on top of it, as
+    /// animations and refreshed images need.
+    fn redraw_in_place(&self, img: &DynamicImage, id: u32) -> DisplayResult {
+        let _ = id;
+        self.display_in_place(img)
+    }
+    /// Deletes the images drawn so far, for protocols that keep them apart from the text.
+    /// Others have their images go away with the text, as the screen is cleared.
+    fn clear(&self) -> DisplayResult {
+        Ok(())
+    }
     fn supported(&self) -> bool;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_policies_move_from_the_corner() {
+        assert_eq!(make_room(1), "");
+        assert_eq!(make_room(3), "\x1bD\x1bD\x1b[2A");
+        assert_eq!(cursor_moves(CursorPolicy::Start, 4, 3), "");
+        assert_eq!(cursor_moves(CursorPolicy::After, 4, 3), "\x1b[4C");
+        assert_eq!(cursor_moves(CursorPolicy::Below, 4, 3), "\x1b[2B\n");
+        assert_eq!(cursor_moves(CursorPolicy::Below, 4, 1), "\n");
+    }
+
+    #[test]
+    fn inline_boxes_make_room_before_drawing() {
+        // A 4x3 box on the last row: two lines scrolled in, back up to the row the box
+        // starts on, then past the box as text would be.
+        let moves = [make_room(3), cursor_moves(CursorPolicy::After, 4, 3)];
+        assert_eq!(moves, ["\x1bD\x1bD\x1b[2A", "\x1b[4C"]);
+    }
+}
@@ -1,10 +1,14 @@
 extern crate base64;
+extern crate termion;
 use image::GenericImageView;
 
 use self::base64::Engine;
-use std::fmt::Write;
+use std::io::{stdout, Write};
 
-use crate::graphic::Graphic;
+use self::termion::cursor::{Restore, Right, Save};
+use crate::graphic::{make_room, DisplayResult, Graphic};
+use crate::term::passthrough;
+use crate::terminal;
 
 pub struct Iterm;
 impl Graphic for Iterm {
@@ -13,23 +17,47 @@ impl Graphic for Iterm {
     }
 
     fn display(&self, img: &image::DynamicImage) -> Result<(), Box<dyn std::error::Error>> {
-        let mut content = vec![];
-        let _ = img.write_to(&mut content,image::ImageOutputFormat::Png);
-        let eng = base64::engine::general_purpose::STANDARD;
-        let encoded = eng.encode(content);
-        let size = encoded.len();
-        let mut str = String::from("\x1b]1337;");
+        let mut stdout = stdout().lock();
         let (w, h) = img.dimensions();
-        write!(&mut str, "File=size={};width={}px;height={}px;inline=1:{}\x07", 
-                       size,
-                       w,
-                       h,encoded)
-            .map_err(|err| Box::new(err))?;
-        println!("{}",str);
+        stdout.write_all(&passthrough(&sequence(img, &format!("{}px", w), &format!("{}px", h))))?;
+        writeln!(stdout)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn encode(&self, img: &image::DynamicImage, out: &mut dyn Write) -> DisplayResult {
+        let (w, h) = img.dimensions();
+        out.write_all(&sequence(img, &format!("{}px", w), &format!("{}px", h)))?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// The terminal scales the image to the box, given in cells.
+    fn display_inline(&self, img: &image::DynamicImage, cols: u16, rows: u16) -> DisplayResult {
+        let mut stdout = stdout().lock();
+        write!(stdout, "{}{}", make_room(rows), Save)?;
+        stdout.write_all(&passthrough(&sequence(img, &cols.to_string(), &rows.to_string())))?;
+        write!(stdout, "{}{}", Restore, Right(cols))?;
+        stdout.flush()?;
         Ok(())
     }
 
     fn supported(&self) -> bool {
-        std::env::var("TERM_PROGRAM").unwrap_or("".to_owned()) == "iTerm.app"
+        const TERMINALS: &[&str] = &["iterm2", "wezterm", "konsole", "vscode", "mintty"];
+        terminal::identify().is_some_and(|t| TERMINALS.contains(&t.name.as_str()))
     }
 }
+
+/// The OSC 1337 sequence that draws `img`, sent as PNG, `width` and `height` wide and
+/// tall: cells, or pixels with a `px` suffix.
+fn sequence(img: &image::DynamicImage, width: &str, height: &str) -> Vec<u8> {
+    let mut content = vec![];
+    let _ = img.write_to(&mut std::io::Cursor::new(&mut content),image::ImageOutputFormat::Png);
+    let eng = base64::engine::general_purpose::STANDARD;
+    let encoded = eng.encode(content);
+    let size = encoded.len();
+    format!("\x1b]1337;File=size={};width={};height={};preserveAspectRatio=1;inline=1:{}\x07",
+                   size,
+                   width,
+                   height,encoded).into_bytes()
+}
@@ -4,6 +4,8 @@ use std::{
     io::{stdout, Read, Stdin, Write},
     os::fd::FromRawFd,
     path::Path,
+    collections::BTreeSet,
+    sync::{Mutex, OnceLock},
     thread,
     time::Duration,
 };
@@ -16,14 +18,16 @@ use self::base64::Engine;
 use self::nix::libc::O_RDWR;
 use self::nix::libc::{O_CREAT, S_IROTH, S_IWUSR};
 use self::nix::libc::{S_IRUSR, S_IXUSR};
-use self::termion::cursor::DetectCursorPos;
+use self::termion::cursor::{DetectCursorPos, Goto, Restore, Right, Save};
 use self::termion::raw::IntoRawMode;
-use image::{DynamicImage, EncodableLayout, GenericImageView};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
 
 use crate::{
     apc::{ControlValue, APC},
-    graphic::{Graphic, TerminalSize},
-    utils::{get_image, has_alpha, prepare_img},
+    graphic::{fit_to_cells, guess_size, make_room, new_image_id, DisplayResult, Graphic, TerminalSize},
+    options::Region,
+    utils::{convert_to_rgb_rgba, get_image, has_alpha, prepare_img},
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -32,23 +36,40 @@ enum KittyError {
     Unsupported,
     #[error("Failed to create shared memory")]
     FailedToCreateSharedMem,
+    #[error("Failed to get the terminal size")]
+    UnknownSize,
+    #[error("Anchored placements need a placement id, and so does their parent")]
+    MissingPlacementId,
 }
 
 pub struct Kitty;
-impl Graphic for Kitty {
-    fn display(&self, img: &DynamicImage) -> Result<(), Box<dyn Error>> {
+impl Kitty {
+    fn show(
+        &self,
+        img: &DynamicImage,
+        position: Position,
+        placement: Placement,
+        id: u32,
+    ) -> Result<(), Box<dyn Error>> {
         let terminal_size = self.size();
         let fns: [(
             bool,
-            fn(&DynamicImage, Option<TerminalSize>) -> Result<(), Box<dyn Error>>,
+            fn(
+                &DynamicImage,
+                Option<TerminalSize>,
+                Position,
+                Placement,
+                u32,
+            ) -> Result<(), Box<dyn Error>>,
         ); 2] = [
             (is_shared_mem_supported(), show_by_shared_memory),
             (is_direct_supported(), show_by_direct_data),
         ];
         for &(supported, f) in &fns {
             if supported {
-                let r = f(img, terminal_size.clone());
+                let r = f(img, terminal_size.clone(), position, placement, id);
                 if r.is_ok() {
+                    SHOWN.lock().unwrap().insert(id);
                     return r;
                 }
             }
@@ -56,6 +77,147 @@ impl Graphic for Kitty {
         Err(Box::new(KittyError::Unsupported))
     }
 
+    /// Draws `img` at the cursor, or next to its parent placement, as `placement` says
+    /// and leaves the cursor where it is. Returns the id of the image, for it to be
+    /// deleted or replaced later, or to anchor other placements to.
+    pub fn place(&self, img: &DynamicImage, placement: &Placement) -> Result<u32, Box<dyn Error>> {
+        placement.check()?;
+        let id = new_image_id();
+        // Only 8-bit RGB and RGBA pixels can be sent as they are.
+        let img = convert_to_rgb_rgba(img.clone());
+        self.show(&img, Position::InPlace, *placement, id)?;
+        Ok(id)
+    }
+
+    /// Adds a placement of the image `image_id`, which [`Kitty::place`] uploaded
+    /// before, without sending it again. The cursor stays where it is.
+    pub fn place_uploaded(&self, image_id: u32, placement: &Placement) -> DisplayResult {
+        placement.check()?;
+        Transimisson::new()
+            .id(image_id)
+            .action(Action::Placement)
+            .cursor_movement(false)
+            .placement(*placement)
+            .send();
+        Ok(())
+    }
+
+    /// Draws `img` under the text and cell backgrounds, covering the whole window, as
+    /// a wallpaper does. The image is cropped to the shape of the window rather than
+    /// stretched. Like text, it scrolls away with the screen. Returns its id.
+    pub fn display_background(&self, img: &DynamicImage) -> Result<u32, Box<dyn Error>> {
+        let size = self
+            .size()
+            .or_else(guess_size)
+            .ok_or(KittyError::UnknownSize)?;
+        let cover = img.resize_to_fill(size.width as u32, size.height as u32, FilterType::Triangle);
+        print!("{}{}", Save, Goto(1, 1));
+        stdout().flush()?;
+        let placement = Placement {
+            cells: Some((size.cols, size.rows)),
+            ..Placement::below_background()
+        };
+        let r = self.place(&cover, &placement);
+        print!("{}", Restore);
+        stdout().flush()?;
+        r
+    }
+}
+impl Graphic for Kitty {
+    fn display(&self, img: &DynamicImage) -> Result<(), Box<dyn Error>> {
+        self.show(img, Position::Centered, Placement::default(), new_image_id())
+    }
+
+    /// Sends the pixels directly, without an id, so that each replay adds an image.
+    fn encode(&self, img: &DynamicImage, out: &mut dyn Write) -> DisplayResult {
+        let (w, h) = img.dimensions();
+        Transimisson::new()
+            .data_format(if has_alpha(img) {
+                DataFormat::RGBA
+            } else {
+                DataFormat::RGB
+            })
+            .action(Action::ImmediatelyShow)
+            .transmission_type(TransmissionType::Direct(img.as_bytes(), (w as u16, h as u16)))
+            .write_to(out)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn display_in_place(&self, img: &DynamicImage) -> DisplayResult {
+        self.show(img, Position::InPlace, Placement::default(), new_image_id())
+    }
+
+    /// The terminal scales the image to the box itself, given in cells, and leaves the
+    /// cursor where it is.
+    fn display_inline(&self, img: &DynamicImage, cols: u16, rows: u16) -> DisplayResult {
+        print!("{}", make_room(rows));
+        let img = fit_to_cells(self, img, cols, rows);
+        let placement = Placement {
+            cells: Some((cols, rows)),
+            ..Placement::default()
+        };
+        self.show(&img, Position::InPlace, placement, new_image_id())?;
+        print!("{}", Right(cols));
+        stdout().flush()?;
+        Ok(())
+    }
+
+    /// Sending an image with the id of one already shown replaces it, placements included.
+    fn redraw_in_place(&self, img: &DynamicImage, id: u32) -> DisplayResult {
+        self.show(img, Position::InPlace, Placement::default(), id)
+    }
+
+    /// The terminal cuts the region out of the image it keeps. The image is sent the
+    /// first time `id` is shown, later regions are only placements of it.
+    fn display_region(&self, img: &DynamicImage, region: Region, id: u32) -> DisplayResult {
+        let region = match region.within(img.width(), img.height()) {
+            Some(region) => region,
+            None => return Ok(()),
+        };
+        let size = self.size();
+        let placement = Placement {
+            source: Some(region),
+            // Scale regions wider than the window down to its width.
+            cells: size
+                .as_ref()
+                .filter(|size| region.width > size.width as u32)
+                .map(|size| {
+                    let (_, cell_height) = size.cell_size();
+                    let height = region.height as f32 * size.width as f32 / region.width as f32;
+                    (size.cols, ((height / cell_height).ceil() as u16).max(1))
+                }),
+            ..Placement::default()
+        };
+        if !UPLOADED.lock().unwrap().contains(&id) {
+            self.show(img, Position::Centered, placement, id)?;
+            UPLOADED.lock().unwrap().insert(id);
+            return Ok(());
+        }
+        if let Some(size) = size {
+            let width = placement.shown_width(region.width, &size);
+            set_showing_position(size, width);
+        }
+        Transimisson::new()
+            .id(id)
+            .action(Action::Placement)
+            .placement(placement)
+            .send();
+        println!();
+        Ok(())
+    }
+
+    /// Deletes only the images this process drew, those of other programs and of
+    /// earlier runs stay.
+    fn clear(&self) -> DisplayResult {
+        let ids = std::mem::take(&mut *SHOWN.lock().unwrap());
+        let deletions = delete_images(&ids);
+        crate::apc::send(&deletions.iter().collect());
+        // Deleting with `d=I` frees the images as well.
+        UPLOADED.lock().unwrap().clear();
+        Ok(())
+    }
+
     fn supported(&self) -> bool {
         is_direct_supported() || is_shared_mem_supported()
     }
@@ -65,70 +227,185 @@ impl Graphic for Kitty {
     }
 }
 
+/// Ids of the images sent by [`Kitty::display_region`], which the terminal keeps until
+/// they are cleared.
+static UPLOADED: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Ids of every image sent, for [`Graphic::clear`] to delete.
+static SHOWN: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// The commands deleting the images `ids` along with their placements and data.
+fn delete_images(ids: &BTreeSet<u32>) -> Vec<APC> {
+    ids.iter()
+        .map(|&id| {
+            let mut apc = APC::new();
+            apc.add_control_field("a", ControlValue::Str("d".to_owned()))
+                .add_control_field("d", ControlValue::Str("I".to_owned()))
+                .add_control_field("i", ControlValue::U32(id))
+                .add_control_field("q", ControlValue::U16(2));
+            apc
+        })
+        .collect()
+}
+
+/// Where an image is drawn relative to the cursor.
+#[derive(Clone, Copy)]
+enum Position {
+    /// Centered on the cursor line, the cursor ends up on the line below the image.
+    Centered,
+    /// At the cursor, which stays where it is.
+    InPlace,
+}
+
+/// Images with a z-index below this are drawn under the background colour of cells,
+/// not only under their text.
+pub const BELOW_BACKGROUND: i32 = -1_073_741_824;
+
+/// How an image is laid out, on top of where the cursor puts it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Placement {
+    /// Box of `cols` x `rows` cells the image is scaled to, its own size when `None`.
+    pub cells: Option<(u16, u16)>,
+    /// Part of the image shown, all of it when `None`.
+    pub source: Option<Region>,
+    /// Stacking order of images and text, which is at 0. Negative values are drawn
+    /// under the text, those below [`BELOW_BACKGROUND`] under cell backgrounds too.
+    pub z_index: i32,
+    /// Id of the placement among those of its image, for other placements to be
+    /// anchored to it; none when 0.
+    pub id: u32,
+    /// The placement this one is positioned relative to, rather than at the cursor.
+    /// Both need an [`Placement::id`] then.
+    pub parent: Option<Anchor>,
+}
+
+/// A placement another one is positioned relative to, moving along with it when it
+/// scrolls. Needs kitty 0.31 or later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    /// Id of the parent image, as [`Kitty::place`] returns it.
+    pub image_id: u32,
+    /// The parent placement's [`Placement::id`], which must not be 0.
+    pub placement_id: u32,
+    /// Offset from the top left corner of the parent, in cells.
+    pub cols: i32,
+    pub rows: i32,
+}
+
+impl Placement {
+    /// Fails for an anchored placement without the ids it needs.
+    fn check(&self) -> Result<(), KittyError> {
+        match self.parent {
+            Some(parent) if parent.placement_id == 0 || self.id == 0 => {
+                Err(KittyError::MissingPlacementId)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// How many pixels wide an image `img_width` pixels wide shows on `size`.
+    fn shown_width(&self, img_width: u32, size: &TerminalSize) -> u32 {
+        match (self.cells, self.source) {
+            (Some((cols, _)), _) => (cols as f32 * size.cell_size().0) as u32,
+            (None, Some(region)) => region.width,
+            (None, None) => img_width,
+        }
+    }
+
+    /// A placement under the text and the cell backgrounds, for a wallpaper or a
+    /// watermark.
+    pub fn below_background() -> Placement {
+        Placement {
+            z_index: BELOW_BACKGROUND - 1,
+            ..Placement::default()
+        }
+    }
+}
+
 fn set_showing_position(size: TerminalSize, img_width: u32) -> () {
-    let TerminalSize {
-        width,
-        height,
-        rows,
-        cols,
-    } = size;
-    let cell_width = (width as f32) / (cols as f32);
-    let left = ((width - img_width as u16) as f32) / 2.0;
-    let cell_index = (left / cell_width) as u16;
-    horizental_move_cur(cell_index);
+    horizental_move_cur(centered_column(&size, img_width));
+}
+
+/// The column an image `img_width` pixels wide starts at to be centred on `size`.
+/// Images wider than the window start at its left edge.
+fn centered_column(size: &TerminalSize, img_width: u32) -> u16 {
+    let cell_width = (size.width as f32) / (size.cols as f32);
+    let left = (size.width as u32).saturating_sub(img_width) as f32 / 2.0;
+    (left / cell_width) as u16
 }
 fn show_by_direct_data(
     img: &DynamicImage,
     size: Option<TerminalSize>,
+    position: Position,
+    placement: Placement,
+    id: u32,
 ) -> Result<(), Box<dyn Error>> {
     let (w, h) = img.dimensions();
-    if size.is_some() {
-        set_showing_position(size.unwrap(), w);
+    if let (Position::Centered, Some(size)) = (position, size) {
+        let width = placement.shown_width(w, &size);
+        set_showing_position(size, width);
     }
     let trans = Transimisson::new()
+        .id(id)
         .data_format(if has_alpha(&img) {
             DataFormat::RGBA
         } else {
             DataFormat::RGB
         })
         .action(Action::ImmediatelyShow)
+        .cursor_movement(matches!(position, Position::Centered))
+        .placement(placement)
         .transmission_type(TransmissionType::Direct(
-            img.to_bytes().as_bytes(),
+            img.as_bytes(),
             (w as u16, h as u16),
         ));
-    trans.transfer();
+    trans.send();
+    if let Position::Centered = position {
+        println!();
+    }
     Ok(())
 }
 
 fn show_by_shared_memory(
     img: &DynamicImage,
     size: Option<TerminalSize>,
+    position: Position,
+    placement: Placement,
+    id: u32,
 ) -> Result<(), Box<dyn Error>> {
     let (w, h) = img.dimensions();
-    if size.is_some() {
-        set_showing_position(size.unwrap(), w);
+    if let (Position::Centered, Some(size)) = (position, size) {
+        let width = placement.shown_width(w, &size);
+        set_showing_position(size, width);
     }
+    let shm_name = format!("__termimg_{}_{}__", std::process::id(), id);
     let trans = Transimisson::new()
+        .id(id)
         .data_format(if has_alpha(&img) {
             DataFormat::RGBA
         } else {
             DataFormat::RGB
         })
         .action(Action::ImmediatelyShow)
+        .cursor_movement(matches!(position, Position::Centered))
+        .placement(placement)
         .transmission_type(TransmissionType::SharedMemory(
-            "__termimg_image_object__".to_owned(),
+            shm_name.clone(),
             (w as u16, h as u16),
         ));
-    let _ = nix::sys::mman::shm_unlink("__termimg_image_object__");
+    let _ = nix::sys::mman::shm_unlink(shm_name.as_str());
     let id = nix::sys::mman::shm_open(
-        "__termimg_image_object__",
+        shm_name.as_str(),
         unsafe { nix::fcntl::OFlag::from_bits_unchecked(O_CREAT | O_RDWR) },
         nix::sys::stat::Mode::from_bits_truncate(S_IRUSR | S_IWUSR | S_IXUSR),
     )?;
     let mut file = unsafe { File::from_raw_fd(id) };
-    file.write(img.to_bytes().as_bytes())
+    file.write(img.as_bytes())
         .map_err(|err| Box::new(err))?;
-    trans.transfer();
+    trans.send();
+    if let Position::Centered = position {
+        println!();
+    }
     Ok(())
 }
 
@@ -179,6 +456,10 @@ fn is_regular_file_supported() -> bool {
     resp.len() > 0 && !resp.contains("ENOTSUPPORTED")
 }
 fn is_direct_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(query_direct_support)
+}
+fn query_direct_support() -> bool {
     let trans = Transimisson::new();
     let resp = trans
         .transmission_type(TransmissionType::Direct(&[255, 255, 255], (1, 1)))
@@ -188,6 +469,10 @@ fn is_direct_supported() -> bool {
     resp.len() > 0 && !resp.contains("ENOTSUPPORTED")
 }
 fn is_shared_mem_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(query_shared_mem_support)
+}
+fn query_shared_mem_support() -> bool {
     let trans = Transimisson::new();
     let resp = trans
         .transmission_type(TransmissionType::SharedMemory("__".to_owned(), (1, 1)))
@@ -201,7 +486,7 @@ fn is_shared_mem_supported() -> bool {
 struct Transimisson {
     apc: APC,
     chunks: Vec<APC>,
-    id: u16,
+    id: u32,
 }
 impl Transimisson {
     fn new() -> Transimisson {
@@ -260,6 +545,10 @@ impl Transimisson {
         };
         self
     }
+    fn id(mut self: Self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
     fn data_format(mut self: Self, fmt: DataFormat) -> Self {
         self.apc.add_control_field("f", get_data_format(fmt));
         self
@@ -275,6 +564,41 @@ impl Transimisson {
         );
         self
     }
+    /// Whether the terminal moves the cursor past the image once it is drawn.
+    fn cursor_movement(mut self: Self, moves: bool) -> Self {
+        if !moves {
+            self.apc.add_control_field("C", ControlValue::U16(1));
+        }
+        self
+    }
+    /// Lays the image out as `placement` says.
+    fn placement(mut self, placement: Placement) -> Self {
+        if let Some((cols, rows)) = placement.cells {
+            self = self.col(cols).row(rows);
+        }
+        if let Some(region) = placement.source {
+            self.apc
+                .add_control_field("x", ControlValue::U32(region.x))
+                .add_control_field("y", ControlValue::U32(region.y))
+                .add_control_field("w", ControlValue::U32(region.width))
+                .add_control_field("h", ControlValue::U32(region.height));
+        }
+        if placement.z_index != 0 {
+            self.apc
+                .add_control_field("z", ControlValue::I32(placement.z_index));
+        }
+        if placement.id != 0 {
+            self.apc.add_control_field("p", ControlValue::U32(placement.id));
+        }
+        if let Some(parent) = placement.parent {
+            self.apc
+                .add_control_field("P", ControlValue::U32(parent.image_id))
+                .add_control_field("Q", ControlValue::U32(parent.placement_id))
+                .add_control_field("H", ControlValue::I32(parent.cols))
+                .add_control_field("V", ControlValue::I32(parent.rows));
+        }
+        self
+    }
     fn row(mut self: Self, row: u16) -> Self {
         self.apc.add_control_field("r", ControlValue::U16(row));
         self
@@ -284,7 +608,7 @@ impl Transimisson {
         self
     }
     fn transfer(mut self: Self) -> String {
-        self.apc.add_control_field("i", ControlValue::U16(self.id));
+        self.apc.add_control_field("i", ControlValue::U32(self.id));
         if self.chunks.len() > 0 {
             let mut apcs = vec![&self.apc];
             for ch in self.chunks.iter() {
@@ -296,4 +620,141 @@ impl Transimisson {
             self.apc.write()
         }
     }
+    /// Writes the transfer to `out` as an image without id, which the terminal does not
+    /// reply to.
+    fn write_to(mut self, out: &mut dyn Write) -> std::io::Result<()> {
+        self.apc.add_control_field("q", ControlValue::U16(2));
+        out.write_all(&self.apc.get())?;
+        for chunk in self.chunks.iter() {
+            out.write_all(&chunk.get())?;
+        }
+        out.flush()
+    }
+    /// Transfers without asking for, nor waiting on, a reply.
+    fn send(mut self: Self) {
+        self.apc.add_control_field("i", ControlValue::U32(self.id));
+        self.apc.add_control_field("q", ControlValue::U16(2));
+        let mut apcs = vec![&self.apc];
+        apcs.extend(self.chunks.iter());
+        crate::apc::send(&apcs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The control fields of the APC `placement` serializes to, sorted.
+    #[test]
+    fn clear_deletes_only_our_images() {
+        let ids = BTreeSet::from([7, 9]);
+        let deletions: Vec<String> = delete_images(&ids)
+            .iter()
+            .map(|apc| String::from_utf8(apc.get()).unwrap())
+            .collect();
+        assert_eq!(deletions.len(), 2);
+        for (deletion, id) in deletions.iter().zip(ids) {
+            assert!(deletion.starts_with("\x1b_G"), "{:?}", deletion);
+            for field in ["a=d", "d=I", &format!("i={}", id)] {
+                assert!(deletion.contains(field), "{:?}", deletion);
+            }
+            assert!(!deletion.contains("d=A"), "{:?}", deletion);
+        }
+    }
+
+    fn control_fields(placement: Placement) -> Vec<String> {
+        let mut out = vec![];
+        Transimisson::new()
+            .action(Action::Placement)
+            .placement(placement)
+            .write_to(&mut out)
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let controls = text
+            .strip_prefix("\x1b_G")
+            .and_then(|rest| rest.split(';').next())
+            .unwrap();
+        let mut fields: Vec<String> = controls.split(',').map(str::to_owned).collect();
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn source_rectangle_is_serialized() {
+        let placement = Placement {
+            source: Some(Region {
+                x: 128,
+                y: 16,
+                width: 64,
+                height: 32,
+            }),
+            cells: Some((8, 2)),
+            ..Placement::default()
+        };
+        assert_eq!(
+            control_fields(placement),
+            ["a=p", "c=8", "h=32", "q=2", "r=2", "w=64", "x=128", "y=16"]
+        );
+    }
+
+    #[test]
+    fn z_index_is_serialized() {
+        assert_eq!(
+            control_fields(Placement::below_background()),
+            ["a=p", "q=2", "z=-1073741825"]
+        );
+        let above = Placement {
+            z_index: 3,
+            ..Placement::default()
+        };
+        assert!(control_fields(above).contains(&"z=3".to_owned()));
+        assert!(!control_fields(Placement::default()).iter().any(|f| f.starts_with("z=")));
+    }
+
+    #[test]
+    fn relative_placement_is_serialized() {
+        let badge = Placement {
+            id: 2,
+            parent: Some(Anchor {
+                image_id: 7,
+                placement_id: 1,
+                cols: -3,
+                rows: 4,
+            }),
+            ..Placement::default()
+        };
+        assert!(badge.check().is_ok());
+        assert_eq!(
+            control_fields(badge),
+            ["H=-3", "P=7", "Q=1", "V=4", "a=p", "p=2", "q=2"]
+        );
+        let orphan = Placement { id: 0, ..badge };
+        assert!(orphan.check().is_err());
+        let unnamed_parent = Placement {
+            parent: badge.parent.map(|p| Anchor { placement_id: 0, ..p }),
+            ..badge
+        };
+        assert!(unnamed_parent.check().is_err());
+    }
+
+    #[test]
+    fn wide_regions_start_at_the_left_edge() {
+        let size = TerminalSize {
+            width: 800,
+            height: 480,
+            cols: 100,
+            rows: 30,
+        };
+        let placement = Placement {
+            source: Some(Region {
+                x: 0,
+                y: 0,
+                width: 70_000,
+                height: 10,
+            }),
+            ..Placement::default()
+        };
+        assert_eq!(centered_column(&size, placement.shown_width(70_000, &size)), 0);
+        assert_eq!(centered_column(&size, 400), 25);
+    }
 }
@@ -5,6 +5,14 @@ pub mod utils;
 pub mod sixel;
 pub mod iterm;
 pub mod term;
+pub mod terminal;
+pub mod options;
+pub mod protocol;
+pub mod metadata;
+pub mod mosaic;
+pub mod animation;
+pub mod stream;
+pub mod transform;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
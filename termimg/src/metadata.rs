@@ -0,0 +1,216 @@
+//! Metadata embedded in image files: the EXIF orientation and the ICC colour profile.
+//!
+//! Each function reads the encoded image from `reader` onwards, leaving it wherever it
+//! stops; callers rewind it to decode the image again.
+use std::io::{BufRead, Seek};
+
+use image::{DynamicImage, ImageFormat};
+
+/// Reads the EXIF orientation of an encoded image, from 1 (upright) to 8.
+#[cfg(feature = "exif")]
+pub fn orientation<R: BufRead + Seek>(reader: &mut R) -> u32 {
+    let exif = match exif::Reader::new().read_from_container(reader) {
+        Ok(exif) => exif,
+        Err(_) => return 1,
+    };
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+#[cfg(not(feature = "exif"))]
+pub fn orientation<R: BufRead + Seek>(_reader: &mut R) -> u32 {
+    1
+}
+
+/// Rotates and flips `img` so that an image tagged with `orientation` shows upright.
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Reads the ICC profile embedded in an encoded image.
+pub fn icc_profile<R: BufRead + Seek>(reader: &mut R, format: ImageFormat) -> Option<Vec<u8>> {
+    #[allow(unused_imports)]
+    use image::ImageDecoder;
+    #[allow(unused_variables)]
+    let cursor = reader;
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(cursor)
+            .ok()?
+            .icc_profile(),
+        #[cfg(feature = "png")]
+        ImageFormat::Png => image::codecs::png::PngDecoder::new(cursor)
+            .ok()?
+            .icc_profile(),
+        #[cfg(feature = "webp")]
+        ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(cursor)
+            .ok()?
+            .icc_profile(),
+        #[cfg(feature = "tiff")]
+        ImageFormat::Tiff => image::codecs::tiff::TiffDecoder::new(cursor)
+            .ok()?
+            .icc_profile(),
+        _ => None,
+    }
+}
+
+/// Decodes a CMYK JPEG, converting it to sRGB through its embedded `icc` profile.
+///
+/// Returns `None` for anything that is not a CMYK JPEG with a CMYK profile; the `image`
+/// crate converts CMYK naively, which is what makes such pictures look washed out.
+#[cfg(all(feature = "icc", feature = "jpeg"))]
+pub fn decode_cmyk_jpeg<R: BufRead + Seek>(reader: &mut R, icc: &[u8]) -> Option<DynamicImage> {
+    use lcms2::{ColorSpaceSignature, Intent, PixelFormat, Profile, Transform};
+
+    let profile = Profile::new_icc(icc).ok()?;
+    if profile.color_space() != ColorSpaceSignature::CmykData {
+        return None;
+    }
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    let pixels = decoder.decode().ok()?;
+    let info = decoder.info()?;
+    if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 {
+        return None;
+    }
+    let transform = Transform::<u8, u8>::new(
+        &profile,
+        PixelFormat::CMYK_8,
+        &Profile::new_srgb(),
+        PixelFormat::RGB_8,
+        Intent::Perceptual,
+    )
+    .ok()?;
+    let mut rgb = vec![0; info.width as usize * info.height as usize * 3];
+    transform.transform_pixels(&pixels, &mut rgb);
+    image::RgbImage::from_raw(info.width as u32, info.height as u32, rgb).map(DynamicImage::ImageRgb8)
+}
+
+#[cfg(not(all(feature = "icc", feature = "jpeg")))]
+pub fn decode_cmyk_jpeg<R: BufRead + Seek>(_reader: &mut R, _icc: &[u8]) -> Option<DynamicImage> {
+    None
+}
+
+/// Converts `img` from the colour space described by the RGB profile `icc` to sRGB.
+///
+/// 16-bit and float images are converted at their own depth, so that the range of
+/// HDR images is kept for tone mapping. Images with an unusable or non-RGB profile are
+/// returned untouched.
+#[cfg(feature = "icc")]
+pub fn to_srgb(img: DynamicImage, icc: &[u8]) -> DynamicImage {
+    use image::ColorType;
+    use lcms2::{ColorSpaceSignature, PixelFormat, Profile};
+
+    let profile = match Profile::new_icc(icc) {
+        Ok(profile) if profile.color_space() == ColorSpaceSignature::RgbData => profile,
+        _ => return img,
+    };
+    let alpha = img.color().has_alpha();
+    match img.color() {
+        ColorType::Rgb32F | ColorType::Rgba32F if alpha => {
+            let mut buf = img.into_rgba32f();
+            convert_samples::<f32, 4>(&profile, PixelFormat::RGBA_FLT, &mut buf);
+            DynamicImage::ImageRgba32F(buf)
+        }
+        ColorType::Rgb32F => {
+            let mut buf = img.into_rgb32f();
+            convert_samples::<f32, 3>(&profile, PixelFormat::RGB_FLT, &mut buf);
+            DynamicImage::ImageRgb32F(buf)
+        }
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 if alpha => {
+            let mut buf = img.into_rgba16();
+            convert_samples::<u16, 4>(&profile, PixelFormat::RGBA_16, &mut buf);
+            DynamicImage::ImageRgba16(buf)
+        }
+        ColorType::L16 | ColorType::Rgb16 => {
+            let mut buf = img.into_rgb16();
+            convert_samples::<u16, 3>(&profile, PixelFormat::RGB_16, &mut buf);
+            DynamicImage::ImageRgb16(buf)
+        }
+        _ if alpha => {
+            let mut buf = img.into_rgba8();
+            convert_samples::<u8, 4>(&profile, PixelFormat::RGBA_8, &mut buf);
+            DynamicImage::ImageRgba8(buf)
+        }
+        _ => {
+            let mut buf = img.into_rgb8();
+            convert_samples::<u8, 3>(&profile, PixelFormat::RGB_8, &mut buf);
+            DynamicImage::ImageRgb8(buf)
+        }
+    }
+}
+
+/// Converts interleaved `samples` of `N` channels from `profile` to sRGB in place,
+/// leaving them as they are if lcms cannot.
+#[cfg(feature = "icc")]
+fn convert_samples<T: lcms2::Pod, const N: usize>(
+    profile: &lcms2::Profile,
+    format: lcms2::PixelFormat,
+    samples: &mut [T],
+) where
+    [T; N]: lcms2::Pod,
+{
+    use lcms2::{Intent, Profile, Transform};
+
+    let transform = match Transform::<[T; N], [T; N]>::new(
+        profile,
+        format,
+        &Profile::new_srgb(),
+        format,
+        Intent::Perceptual,
+    ) {
+        Ok(transform) => transform,
+        Err(_) => return,
+    };
+    let mut pixels: Vec<[T; N]> = samples
+        .chunks_exact(N)
+        .map(|chunk| chunk.try_into().unwrap())
+        .collect();
+    transform.transform_in_place(&mut pixels);
+    for (dst, src) in samples.chunks_exact_mut(N).zip(&pixels) {
+        dst.copy_from_slice(src);
+    }
+}
+
+#[cfg(not(feature = "icc"))]
+pub fn to_srgb(img: DynamicImage, _icc: &[u8]) -> DynamicImage {
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    #[cfg(feature = "icc")]
+    fn to_srgb_keeps_float_range() {
+        let srgb = lcms2::Profile::new_srgb().icc().unwrap();
+        let hdr = image::Rgb32FImage::from_pixel(1, 1, image::Rgb([4.0, 2.0, 1.0]));
+        let img = to_srgb(DynamicImage::ImageRgb32F(hdr), &srgb);
+        assert_eq!(img.color(), image::ColorType::Rgb32F);
+        let [r, g, b] = img.into_rgb32f().get_pixel(0, 0).0;
+        assert!((r - 4.0).abs() < 0.05 && (g - 2.0).abs() < 0.05 && (b - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn orientation_turns_image_upright() {
+        // A 2x1 image stored rotated a quarter turn counter-clockwise (orientation 6).
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(1, 2, |_, y| {
+            image::Rgb([y as u8, 0, 0])
+        }));
+        let upright = apply_orientation(img, 6);
+        assert_eq!(upright.dimensions(), (2, 1));
+        assert_eq!(upright.get_pixel(0, 0).0[0], 1);
+    }
+}
@@ -0,0 +1,139 @@
+extern crate ansi_term;
+extern crate termion;
+use std::io::{stdout, Write};
+
+use self::ansi_term::Colour::{Fixed, RGB};
+use self::termion::cursor::{Down, Left, Restore, Save};
+use self::termion::terminal_size;
+use image::{DynamicImage, RgbImage};
+
+use crate::graphic::{DisplayResult, Graphic, TerminalSize};
+use crate::options::{Background, ColorDepth};
+use crate::utils::composite;
+
+/// Draws images with coloured half block characters, so it works on any terminal with
+/// 24-bit colours, or the 256 colour palette. Each cell shows two pixels, one above the
+/// other.
+#[derive(Default)]
+pub struct Mosaic {
+    pub color_depth: ColorDepth,
+}
+impl Graphic for Mosaic {
+    fn name(&self) -> &'static str {
+        "mosaic"
+    }
+
+    /// A pixel is half a cell, so images are laid out in half blocks rather than pixels.
+    fn size(&self) -> Option<TerminalSize> {
+        let (cols, rows) = terminal_size().ok()?;
+        Some(TerminalSize {
+            width: cols,
+            height: rows * 2,
+            cols,
+            rows,
+        })
+    }
+
+    fn display(&self, img: &DynamicImage) -> DisplayResult {
+        let mut stdout = stdout().lock();
+        for line in render_lines(img, self.color_depth) {
+            writeln!(stdout, "{}", line)?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn encode(&self, img: &DynamicImage, out: &mut dyn Write) -> DisplayResult {
+        let img = composite(img.clone(), Background::Color([0, 0, 0]));
+        for line in render_lines(&img, self.color_depth) {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn display_in_place(&self, img: &DynamicImage) -> DisplayResult {
+        let mut stdout = stdout().lock();
+        let width = img.width() as u16;
+        write!(stdout, "{}", Save)?;
+        for line in render_lines(img, self.color_depth) {
+            write!(stdout, "{}{}{}", line, Left(width), Down(1))?;
+        }
+        write!(stdout, "{}", Restore)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn supported(&self) -> bool {
+        true
+    }
+}
+
+/// Renders `img` as one string of half blocks per two rows of pixels.
+pub fn render_lines(img: &DynamicImage, depth: ColorDepth) -> Vec<String> {
+    let colour = |[r, g, b]: [u8; 3]| match depth {
+        ColorDepth::TrueColor => RGB(r, g, b),
+        ColorDepth::Ansi256 => Fixed(ansi256([r, g, b])),
+    };
+    let img: RgbImage = composite(img.clone(), Background::Terminal).to_rgb8();
+    (0..img.height())
+        .step_by(2)
+        .map(|y| {
+            (0..img.width())
+                .map(|x| {
+                    let top = img.get_pixel(x, y).0;
+                    let fg = colour(top);
+                    if y + 1 < img.height() {
+                        let bottom = img.get_pixel(x, y + 1).0;
+                        fg.on(colour(bottom))
+                            .paint("\u{2580}")
+                            .to_string()
+                    } else {
+                        fg.paint("\u{2580}").to_string()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Levels of the red, green and blue axes of the palette's 6x6x6 colour cube.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The colour of the 256 colour palette nearest to `rgb`, from its colour cube or its
+/// grey ramp.
+pub fn ansi256(rgb: [u8; 3]) -> u8 {
+    let nearest_level = |c: u8| {
+        (0..6)
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs())
+            .unwrap_or(0)
+    };
+    let [r, g, b] = rgb.map(nearest_level);
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let average = (rgb.iter().map(|&c| c as u32).sum::<u32>() / 3) as u8;
+    // Greys run from 8 to 238 by steps of 10.
+    let grey_index = ((average.max(8) - 8) as u32 / 10).min(23) as u8;
+    let grey = 8 + 10 * grey_index;
+    let distance = |c: [u8; 3]| {
+        (0..3)
+            .map(|i| (c[i] as i32 - rgb[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+    if distance([grey; 3]) < distance(cube) {
+        232 + grey_index
+    } else {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi256_picks_cube_or_grey() {
+        assert_eq!(ansi256([255, 0, 0]), 196);
+        assert_eq!(ansi256([0, 0, 0]), 16);
+        assert_eq!(ansi256([128, 128, 128]), 244);
+        assert_eq!(ansi256([255, 255, 255]), 231);
+    }
+}
@@ -0,0 +1,439 @@
+use std::str::FromStr;
+
+use image::imageops::FilterType;
+
+use crate::graphic::TerminalSize;
+use crate::transform::Transform;
+
+/// Images are never scaled up past this many pixels wide or high, whatever the size
+/// asked for: such buffers would not fit in memory, let alone on a screen.
+pub const MAX_SIDE: u32 = 8192;
+
+/// What transparent pixels are blended against before an image is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Color([u8; 3]),
+    /// Light and dark grey squares of the given size in pixels, as image editors draw.
+    Checkerboard(u32),
+    /// The terminal's own background colour, asked for with OSC 11.
+    Terminal,
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    /// Parses `terminal`, `checkerboard`, `checkerboard:<size>` or a `#rrggbb` colour.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminal" => Ok(Background::Terminal),
+            "checkerboard" => Ok(Background::Checkerboard(8)),
+            _ => {
+                if let Some(size) = s.strip_prefix("checkerboard:") {
+                    return size
+                        .parse()
+                        .ok()
+                        .filter(|&size| size > 0)
+                        .map(Background::Checkerboard)
+                        .ok_or_else(|| format!("Invalid checkerboard size: {}", size));
+                }
+                parse_color(s)
+                    .map(Background::Color)
+                    .ok_or_else(|| format!("Invalid background: {}", s))
+            }
+        }
+    }
+}
+
+/// Parses a `#rrggbb` (or `rrggbb`) colour.
+pub fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// A width or height asked for an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    Cells(u32),
+    Pixels(u32),
+    /// Percentage of the terminal width or height.
+    Percent(f64),
+}
+
+impl Dimension {
+    /// The length in pixels, given the cell size and the terminal size along the same
+    /// axis. Percentages need the latter.
+    pub fn to_pixels(self, cell: f64, terminal: Option<u32>) -> Option<f64> {
+        match self {
+            Dimension::Cells(n) => Some(n as f64 * cell),
+            Dimension::Pixels(n) => Some(n as f64),
+            Dimension::Percent(p) => terminal.map(|total| total as f64 * p / 100.0),
+        }
+    }
+}
+
+impl FromStr for Dimension {
+    type Err = String;
+
+    /// Parses a number of cells (`40`), pixels (`300px`) or a percentage (`50%`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid size: {}", s);
+        let dimension = if let Some(p) = s.strip_suffix('%') {
+            Dimension::Percent(p.parse().map_err(|_| invalid())?)
+        } else if let Some(px) = s.strip_suffix("px") {
+            Dimension::Pixels(px.parse().map_err(|_| invalid())?)
+        } else {
+            Dimension::Cells(s.parse().map_err(|_| invalid())?)
+        };
+        match dimension {
+            Dimension::Cells(0) | Dimension::Pixels(0) => Err(invalid()),
+            Dimension::Percent(p) if !p.is_finite() || p <= 0.0 => Err(invalid()),
+            _ => Ok(dimension),
+        }
+    }
+}
+
+/// How an image without an explicit width or height is fitted to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Shrink images wider than the terminal.
+    Width,
+    /// Shrink images so that they fit on one screen.
+    Screen,
+    /// Show images at their own size.
+    None,
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "width" => Ok(Fit::Width),
+            "screen" => Ok(Fit::Screen),
+            "none" => Ok(Fit::None),
+            _ => Err(format!("Invalid fit: {}, expected width, screen or none", s)),
+        }
+    }
+}
+
+/// How many colours text output, such as mosaics, may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDepth {
+    #[default]
+    TrueColor,
+    /// The 256 colour palette of xterm, for terminals without 24-bit colours.
+    Ansi256,
+}
+
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            "256" | "8bit" => Ok(ColorDepth::Ansi256),
+            _ => Err(format!("Invalid colour depth: {}, expected truecolor or 256", s)),
+        }
+    }
+}
+
+/// Where the cursor is left once an image is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorPolicy {
+    /// Just right of the image's top row, where text following it would go.
+    After,
+    /// Where it was, at the image's top left corner.
+    Start,
+    /// At the start of the line below the image.
+    Below,
+}
+
+impl FromStr for CursorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "after" => Ok(CursorPolicy::After),
+            "start" => Ok(CursorPolicy::Start),
+            "below" => Ok(CursorPolicy::Below),
+            _ => Err(format!("Invalid cursor policy: {}, expected after, start or below", s)),
+        }
+    }
+}
+
+/// A rectangle of an image, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// The part of this region that lies in an image of `width` x `height` pixels,
+    /// `None` when nothing does.
+    pub fn within(self, width: u32, height: u32) -> Option<Region> {
+        let right = self.x.saturating_add(self.width).min(width);
+        let bottom = self.y.saturating_add(self.height).min(height);
+        if self.x >= right || self.y >= bottom {
+            return None;
+        }
+        Some(Region {
+            x: self.x,
+            y: self.y,
+            width: right - self.x,
+            height: bottom - self.y,
+        })
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Parses a `WxH+X+Y` geometry, such as `64x64+128+0`; the offset may be left out.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid region: {}, expected WxH+X+Y", s);
+        let (size, offset) = match s.find('+') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, "0+0"),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+        let number = |n: &str| n.trim().parse::<u32>().map_err(|_| invalid());
+        let region = Region {
+            x: number(x)?,
+            y: number(y)?,
+            width: number(width)?,
+            height: number(height)?,
+        };
+        if region.width == 0 || region.height == 0 {
+            return Err(invalid());
+        }
+        Ok(region)
+    }
+}
+
+/// Parses the name of a resampling filter: `nearest`, `triangle`, `catmull-rom`,
+/// `gaussian` or `lanczos3`.
+pub fn parse_filter(s: &str) -> Option<FilterType> {
+    match s {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" | "linear" => Some(FilterType::Triangle),
+        "catmull-rom" | "cubic" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" | "lanczos" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+/// Parses a scale factor, a finite number above 0.
+pub fn parse_scale(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(factor),
+        _ => Err(format!("Invalid scale: {}, expected a factor above 0", s)),
+    }
+}
+
+/// Options describing how an image is loaded and laid out on the terminal.
+#[derive(Debug, Clone)]
+pub struct DisplayOptions {
+    /// Size of the terminal the image is shown in. Images are not scaled when `None`.
+    pub size: Option<TerminalSize>,
+    /// Colour an SVG is rendered on top of; its background stays transparent when `None`.
+    pub svg_background: Option<[u8; 3]>,
+    /// Resolution used to resolve physical units (`mm`, `in`, `pt`) in SVGs, 96 when `None`.
+    pub dpi: Option<f64>,
+    /// Rotate and flip images according to their EXIF orientation.
+    pub auto_orient: bool,
+    /// Convert images with an embedded ICC profile to sRGB.
+    pub color_manage: bool,
+    /// Flatten transparent images against this background. When `None`, protocols that
+    /// handle alpha get it as is and the others flatten against the terminal background.
+    pub background: Option<Background>,
+    /// Width to scale images to, keeping their aspect ratio unless `height` is set too.
+    pub width: Option<Dimension>,
+    /// Height to scale images to. With `width`, images fit in that box.
+    pub height: Option<Dimension>,
+    /// Factor applied to the image size when neither `width` nor `height` is set.
+    pub scale: Option<f64>,
+    /// How images without an explicit size are fitted to the terminal.
+    pub fit: Fit,
+    /// Width images are never scaled above, whatever else asks.
+    pub max_width: Option<Dimension>,
+    /// Height images are never scaled above, whatever else asks.
+    pub max_height: Option<Dimension>,
+    /// Allow scaling images above their own size.
+    pub upscale: bool,
+    /// Filter used when scaling images.
+    pub filter: FilterType,
+    /// Where to leave the cursor after an image. When `None`, each protocol leaves it
+    /// where it does, below the image mostly.
+    pub cursor: Option<CursorPolicy>,
+    /// Part of the image to show, in pixels of the decoded image, before it is scaled.
+    ///
+    /// The region is cut out on the CPU for every protocol, so that it is scaled,
+    /// transformed and blended like a whole image and only its pixels are sent. To
+    /// show several regions of one image uploaded once, use
+    /// [`Graphic::display_region`](crate::graphic::Graphic::display_region) instead.
+    pub crop: Option<Region>,
+    /// Run on the image, once cropped, before it is scaled.
+    pub transforms: Vec<Transform>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions {
+            size: None,
+            svg_background: None,
+            dpi: None,
+            auto_orient: true,
+            color_manage: true,
+            background: None,
+            width: None,
+            height: None,
+            scale: None,
+            fit: Fit::Width,
+            max_width: None,
+            max_height: None,
+            upscale: true,
+            filter: FilterType::Nearest,
+            cursor: None,
+            crop: None,
+            transforms: vec![],
+        }
+    }
+}
+
+impl DisplayOptions {
+    pub fn new(size: Option<TerminalSize>) -> DisplayOptions {
+        DisplayOptions {
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// The pixel size an image of `width` x `height` pixels is displayed at.
+    pub fn fit_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let size = self.size.as_ref().filter(|size| size.width > 0 && size.height > 0);
+        let (cell_width, cell_height) = match size {
+            Some(size) => size.cell_size(),
+            None => (
+                TerminalSize::DEFAULT_CELL_SIZE.0 as f32,
+                TerminalSize::DEFAULT_CELL_SIZE.1 as f32,
+            ),
+        };
+        let box_width = box_size(self.width, cell_width, size.map(|s| s.width));
+        let box_height = box_size(self.height, cell_height, size.map(|s| s.height));
+        let (w, h) = (width.max(1) as f64, height.max(1) as f64);
+        let mut factor = match (box_width, box_height) {
+            (Some(bw), Some(bh)) => (bw / w).min(bh / h),
+            (Some(bw), None) => bw / w,
+            (None, Some(bh)) => bh / h,
+            (None, None) => {
+                let factor = self.scale.unwrap_or(1.0);
+                match (size, self.fit) {
+                    (Some(size), Fit::Width) => factor.min(size.width as f64 / w),
+                    (Some(size), Fit::Screen) => {
+                        // Leave a line for the prompt, lest the top scrolls away.
+                        let height = (size.height as f64 - cell_height as f64).max(1.0);
+                        factor.min(size.width as f64 / w).min(height / h)
+                    }
+                    _ => factor,
+                }
+            }
+        };
+        if !self.upscale {
+            factor = factor.min(1.0);
+        }
+        if let Some(max) = box_size(self.max_width, cell_width, size.map(|s| s.width)) {
+            factor = factor.min(max / w);
+        }
+        if let Some(max) = box_size(self.max_height, cell_height, size.map(|s| s.height)) {
+            factor = factor.min(max / h);
+        }
+        factor = factor.min((MAX_SIDE as f64 / w.max(h)).max(1.0));
+        if factor == 1.0 {
+            return (width, height);
+        }
+        (
+            ((w * factor).round() as u32).max(1),
+            ((h * factor).round() as u32).max(1),
+        )
+    }
+}
+
+fn box_size(dimension: Option<Dimension>, cell: f32, terminal: Option<u16>) -> Option<f64> {
+    dimension?.to_pixels(cell as f64, terminal.map(u32::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal() -> Option<TerminalSize> {
+        Some(TerminalSize {
+            width: 800,
+            height: 480,
+            cols: 100,
+            rows: 30,
+        })
+    }
+
+    #[test]
+    fn fit_size_shrinks_to_terminal_width() {
+        let opts = DisplayOptions::new(terminal());
+        assert_eq!(opts.fit_size(1600, 200), (800, 100));
+        assert_eq!(opts.fit_size(400, 1000), (400, 1000));
+    }
+
+    #[test]
+    fn fit_size_honours_explicit_dimensions() {
+        let mut opts = DisplayOptions::new(terminal());
+        opts.width = Some("20".parse().unwrap());
+        assert_eq!(opts.fit_size(400, 200), (160, 80));
+        opts.height = Some("50%".parse().unwrap());
+        assert_eq!(opts.fit_size(100, 1000), (24, 240));
+        opts.width = Some("1000px".parse().unwrap());
+        opts.height = None;
+        opts.upscale = false;
+        assert_eq!(opts.fit_size(400, 200), (400, 200));
+        opts.max_height = Some("10".parse().unwrap());
+        assert_eq!(opts.fit_size(400, 200), (320, 160));
+    }
+
+    #[test]
+    fn absurd_sizes_are_refused_or_capped() {
+        assert!("inf%".parse::<Dimension>().is_err());
+        assert!("NaN%".parse::<Dimension>().is_err());
+        assert!("-5%".parse::<Dimension>().is_err());
+        let mut opts = DisplayOptions::new(terminal());
+        opts.width = Some("100000".parse().unwrap());
+        assert_eq!(opts.fit_size(400, 200), (MAX_SIDE, MAX_SIDE / 2));
+        assert_eq!(opts.fit_size(10000, 100), (10000, 100));
+        for scale in ["0", "-1", "inf", "NaN", "x"] {
+            assert!(parse_scale(scale).is_err(), "{}", scale);
+        }
+        assert_eq!(parse_scale("1.5"), Ok(1.5));
+    }
+
+    #[test]
+    fn parse_region() {
+        let region = |x, y, width, height| Region {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!("64x32+128+16".parse(), Ok(region(128, 16, 64, 32)));
+        assert_eq!("64x32".parse(), Ok(region(0, 0, 64, 32)));
+        assert!("64x0+1+1".parse::<Region>().is_err());
+        assert!("64+1+1".parse::<Region>().is_err());
+        assert_eq!(region(90, 0, 20, 20).within(100, 10), Some(region(90, 0, 10, 10)));
+        assert_eq!(region(100, 0, 20, 20).within(100, 10), None);
+    }
+}
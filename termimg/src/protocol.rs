@@ -0,0 +1,150 @@
+//! Choosing the graphics protocol images are shown with.
+use std::str::FromStr;
+
+use crate::graphic::Graphic;
+use crate::iterm::Iterm;
+use crate::kitty::Kitty;
+use crate::mosaic::Mosaic;
+use crate::options::ColorDepth;
+use crate::sixel::Sixel;
+use crate::terminal;
+
+/// The environment variable holding a [`Preference`].
+pub const PROTOCOL_ENV: &str = "TERMIMG_PROTOCOL";
+
+/// Which protocol the user asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preference {
+    /// The best one the terminal is known or probed to handle.
+    Auto,
+    /// This one, without asking the terminal whether it handles it.
+    Forced(String),
+    /// The first of these the terminal handles, probed in order.
+    Ordered(Vec<String>),
+}
+
+impl FromStr for Preference {
+    type Err = String;
+
+    /// Parses `auto`, a protocol name, which forces it, or a comma separated list.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let names: Vec<String> = s
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        match names.as_slice() {
+            [] => Err("No protocol given".to_owned()),
+            [name] if name == "auto" => Ok(Preference::Auto),
+            [name] => Ok(Preference::Forced(name.clone())),
+            _ => Ok(Preference::Ordered(names)),
+        }
+    }
+}
+
+impl Preference {
+    /// The preference set in [`PROTOCOL_ENV`], if any.
+    pub fn from_env() -> Option<Result<Preference, String>> {
+        std::env::var(PROTOCOL_ENV)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| value.parse())
+    }
+}
+
+/// Every backend, in the order they are probed when the terminal is not known. Text
+/// output is limited to `color_depth`.
+pub fn backends(color_depth: ColorDepth) -> Vec<Box<dyn Graphic>> {
+    vec![
+        Box::new(Kitty {}),
+        Box::new(Iterm {}),
+        Box::new(Sixel {}),
+        Box::new(Mosaic { color_depth }),
+    ]
+}
+
+/// Picks the backend among `backends` that `preference` asks for.
+///
+/// Forced protocols are taken as they are. Otherwise the first backend found to work
+/// on the terminal is. Only the automatic choice falls back on any that works, mosaic
+/// in the end: when none of an ordered list works, that is an error.
+pub fn resolve<'a>(
+    backends: &'a [Box<dyn Graphic>],
+    preference: &Preference,
+) -> Result<&'a dyn Graphic, String> {
+    let by_name = |name: &str| {
+        backends
+            .iter()
+            .find(|b| b.name() == name)
+            .map(|b| b.as_ref())
+            .ok_or_else(|| format!("Unknown protocol: {}", name))
+    };
+    let names: Vec<&str> = match preference {
+        Preference::Forced(name) => return by_name(name),
+        Preference::Ordered(names) => names.iter().map(String::as_str).collect(),
+        Preference::Auto => {
+            terminal::preferred_protocols(terminal::identify().as_ref()).to_vec()
+        }
+    };
+    for name in &names {
+        let backend = by_name(name)?;
+        if backend.supported() {
+            return Ok(backend);
+        }
+    }
+    if let Preference::Ordered(_) = preference {
+        return Err(format!("None of the protocols {} is supported", names.join(", ")));
+    }
+    backends
+        .iter()
+        .find(|b| b.supported())
+        .map(|b| b.as_ref())
+        .ok_or_else(|| "No protocol is supported".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_preference() {
+        assert_eq!("auto".parse(), Ok(Preference::Auto));
+        assert_eq!("Sixel".parse(), Ok(Preference::Forced("sixel".to_owned())));
+        assert_eq!(
+            "sixel, kitty".parse(),
+            Ok(Preference::Ordered(vec!["sixel".to_owned(), "kitty".to_owned()]))
+        );
+        assert!(" ".parse::<Preference>().is_err());
+    }
+
+    /// A backend no terminal handles.
+    struct Missing;
+
+    impl Graphic for Missing {
+        fn name(&self) -> &'static str {
+            "missing"
+        }
+        fn display(&self, _: &image::DynamicImage) -> crate::graphic::DisplayResult {
+            Ok(())
+        }
+        fn supported(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn unsupported_order_is_an_error() {
+        let backends: Vec<Box<dyn Graphic>> = vec![
+            Box::new(Missing),
+            Box::new(Mosaic {
+                color_depth: ColorDepth::TrueColor,
+            }),
+        ];
+        let name = |preference: &str| resolve(&backends, &preference.parse().unwrap()).map(|b| b.name());
+        assert_eq!(name("missing,mosaic"), Ok("mosaic"));
+        assert_eq!(
+            name("missing,missing"),
+            Err("None of the protocols missing, missing is supported".to_owned())
+        );
+    }
+}
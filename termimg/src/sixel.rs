@@ -1,20 +1,20 @@
 extern crate sixel_rs;
-use std::io::stdout;
-use std::path::Path;
+use std::io::{stdout, Write};
 
 use self::sixel_rs::sys::PixelFormat;
 use image::{DynamicImage, GenericImageView};
 
 use crate::graphic::{DisplayResult, Graphic};
-use crate::term::{self, write};
-use crate::utils::{get_image, has_alpha, prepare_img};
+use crate::term::{self, passthrough, write};
+use crate::options::Background;
+use crate::utils::{composite, get_image, has_alpha, prepare_img};
 //https://vt100.net/docs/vt3xx-gp/chapter14.html
 // xterm -ti vt340
 #[derive(thiserror::Error, Debug)]
 enum SixelError {
     #[error("Failed to initialize encoder")]
     FailedToGetEncoder,
-    #[error("Faield to encode")]
+    #[error("Failed to encode")]
     FailedToEncode,
 }
 
@@ -25,33 +25,46 @@ impl Graphic for Sixel {
     }
 
     fn display(&self, img: &DynamicImage) -> DisplayResult {
-        let terminal_size = self.size();
-        let encoder = sixel_rs::encoder::Encoder::new();
-        let tmp_file = Path::new("/tmp/sixel.output");
-        let _ = std::fs::remove_file(tmp_file);
-        match encoder {
-            Err(err) => Err(Box::new(SixelError::FailedToGetEncoder)),
-            Ok(encoder) => {
-                let (w, h) = img.dimensions();
-                encoder.set_output(&tmp_file);
-                encoder
-                    .encode_bytes(
-                        sixel_rs::encoder::QuickFrameBuilder::new()
-                            .width(w as usize)
-                            .height(h as usize)
-                            .format(if has_alpha(&img) {
-                                PixelFormat::RGBA8888
-                            } else {
-                                PixelFormat::RGB888
-                            })
-                            .pixels(img.to_bytes()),
-                    )
-                    .map_err(|_| -> DisplayResult { Err(Box::new(SixelError::FailedToEncode)) });
-                let mut file = std::fs::File::open(tmp_file).unwrap();
-                std::io::copy(&mut file, &mut stdout().lock());
-                Ok(())
-            }
-        }
+        // Sixel has no alpha channel, blend transparent pixels like the terminal would.
+        let img = composite(img.clone(), Background::Terminal);
+        let mut data = vec![];
+        self.encode(&img, &mut data)?;
+        let mut stdout = stdout().lock();
+        stdout.write_all(&passthrough(&data))?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn encode(&self, img: &DynamicImage, out: &mut dyn Write) -> DisplayResult {
+        let img = &composite(img.clone(), Background::Color([0, 0, 0]));
+        let encoder =
+            sixel_rs::encoder::Encoder::new().map_err(|_| SixelError::FailedToGetEncoder)?;
+        // libsixel writes to a file; one per process, so that runs side by side do not
+        // read each other's.
+        let tmp_file = std::env::temp_dir().join(format!("termimg-sixel-{}", std::process::id()));
+        let _ = std::fs::remove_file(&tmp_file);
+        let (w, h) = img.dimensions();
+        encoder
+            .set_output(&tmp_file)
+            .map_err(|_| SixelError::FailedToEncode)?;
+        let encoded = encoder
+            .encode_bytes(
+                sixel_rs::encoder::QuickFrameBuilder::new()
+                    .width(w as usize)
+                    .height(h as usize)
+                    .format(if has_alpha(img) {
+                        PixelFormat::RGBA8888
+                    } else {
+                        PixelFormat::RGB888
+                    })
+                    .pixels(img.as_bytes().to_vec()),
+            )
+            .map_err(|_| SixelError::FailedToEncode)
+            .and_then(|_| std::fs::read(&tmp_file).map_err(|_| SixelError::FailedToEncode));
+        let _ = std::fs::remove_file(&tmp_file);
+        out.write_all(&encoded?)?;
+        out.flush()?;
+        Ok(())
     }
 
     fn supported(&self) -> bool {
@@ -0,0 +1,182 @@
+//! Splitting a byte stream that carries one image after another, such as the output
+//! of `ffmpeg -f image2pipe`, into single images.
+//!
+//! Each image in the stream is one of:
+//! - a PNG file, which ends with its `IEND` chunk,
+//! - a JPEG file, which ends with its EOI marker, as in an MJPEG stream,
+//! - any other image prefixed with its length in bytes as a big endian `u32`.
+use std::io::{self, BufReader, ErrorKind, Read};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Images larger than this, whatever their length prefix, chunk or segment lengths
+/// say, are taken for a corrupt stream.
+const MAX_FRAME_LEN: u32 = 256 << 20;
+
+pub struct ImageStream<R> {
+    reader: BufReader<R>,
+}
+
+impl<R: Read> ImageStream<R> {
+    pub fn new(reader: R) -> ImageStream<R> {
+        ImageStream {
+            reader: BufReader::new(reader),
+        }
+    }
+
+    /// The bytes of the next image, or `None` once the stream ends between images.
+    pub fn next_image(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut frame = vec![0; 2];
+        match self.reader.read(&mut frame[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut frame[1..])?,
+        }
+        match frame[..] {
+            [0x89, b'P'] => self.read_png(&mut frame)?,
+            [0xff, 0xd8] => self.read_jpeg(&mut frame)?,
+            _ => {
+                self.read_to(&mut frame, 2)?;
+                let len = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+                if len > MAX_FRAME_LEN {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown image in stream ({} bytes long?)", len),
+                    ));
+                }
+                frame.clear();
+                self.read_to(&mut frame, len as usize)?;
+            }
+        }
+        Ok(Some(frame))
+    }
+
+    fn read_png(&mut self, frame: &mut Vec<u8>) -> io::Result<()> {
+        self.read_to(frame, PNG_SIGNATURE.len() - 2)?;
+        if frame[..] != *PNG_SIGNATURE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "bad PNG signature"));
+        }
+        loop {
+            let start = frame.len();
+            self.read_to(frame, 8)?;
+            let mut len = [0; 4];
+            len.copy_from_slice(&frame[start..start + 4]);
+            let len = u32::from_be_bytes(len);
+            // Chunk data and CRC.
+            self.read_to(frame, len as usize + 4)?;
+            if &frame[start + 4..start + 8] == b"IEND" {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads segment after segment up to the EOI marker. The compressed data following
+    /// SOS has no length, but any 0xff in it is followed by 0x00 or a restart marker.
+    fn read_jpeg(&mut self, frame: &mut Vec<u8>) -> io::Result<()> {
+        loop {
+            match self.next_marker(frame)? {
+                // EOI
+                0xd9 => return Ok(()),
+                // Stuffed 0xff in compressed data, restart markers, TEM, SOI.
+                0x00 | 0x01 | 0xd0..=0xd8 => {}
+                _ => {
+                    let start = frame.len();
+                    self.read_to(frame, 2)?;
+                    let len = u16::from_be_bytes([frame[start], frame[start + 1]]);
+                    self.read_to(frame, (len as usize).saturating_sub(2))?;
+                }
+            }
+        }
+    }
+
+    /// Copies bytes up to the next marker into `frame`, returning the marker's code.
+    fn next_marker(&mut self, frame: &mut Vec<u8>) -> io::Result<u8> {
+        let mut byte = [0];
+        loop {
+            self.reader.read_exact(&mut byte)?;
+            frame.push(byte[0]);
+            if frame.len() > MAX_FRAME_LEN as usize {
+                return Err(too_long());
+            }
+            if byte[0] != 0xff {
+                continue;
+            }
+            // Any number of 0xff may pad a marker.
+            while byte[0] == 0xff {
+                self.reader.read_exact(&mut byte)?;
+                frame.push(byte[0]);
+            }
+            return Ok(byte[0]);
+        }
+    }
+
+    fn read_to(&mut self, frame: &mut Vec<u8>, len: usize) -> io::Result<()> {
+        let start = frame.len();
+        if start + len > MAX_FRAME_LEN as usize {
+            return Err(too_long());
+        }
+        frame.resize(start + len, 0);
+        self.reader.read_exact(&mut frame[start..])
+    }
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("image in stream over {} bytes long", MAX_FRAME_LEN),
+    )
+}
+
+impl<R: Read> Iterator for ImageStream<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_image().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn encode(format: ImageOutputFormat) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 8, |x, y| {
+            image::Rgb([x as u8 * 16, y as u8 * 32, 255])
+        }));
+        let mut data = Cursor::new(vec![]);
+        img.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn splits_concatenated_images() {
+        let png = encode(ImageOutputFormat::Png);
+        let jpeg = encode(ImageOutputFormat::Jpeg(90));
+        let bmp = encode(ImageOutputFormat::Bmp);
+        let mut data = [&png[..], &jpeg, &png].concat();
+        data.extend((bmp.len() as u32).to_be_bytes());
+        data.extend(&bmp);
+        let frames: Vec<Vec<u8>> = ImageStream::new(&data[..]).map(Result::unwrap).collect();
+        assert_eq!(frames, vec![png.clone(), jpeg, png, bmp]);
+    }
+
+    #[test]
+    fn truncated_image_is_an_error() {
+        let png = encode(ImageOutputFormat::Png);
+        let mut stream = ImageStream::new(&png[..png.len() - 3]);
+        assert_eq!(
+            stream.next_image().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn oversized_chunk_is_an_error() {
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend(u32::MAX.to_be_bytes());
+        data.extend(b"IDAT");
+        let mut stream = ImageStream::new(&data[..]);
+        assert_eq!(stream.next_image().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,20 +1,208 @@
+extern crate nix;
 extern crate termion;
-use std::io::{Read, stdout, Write};
-use std::thread;
-use std::time::Duration;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::os::fd::AsRawFd;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
+use self::nix::poll::{poll, PollFd, PollFlags};
 use self::termion::raw::IntoRawMode;
-use self::termion::async_stdin;
 
+/// Screen drops strings longer than this, so longer sequences are sent in pieces.
+const SCREEN_CHUNK_LEN: usize = 760;
+
+/// A terminal multiplexer sitting between the program and the terminal, which keeps
+/// the escape sequences it does not know about to itself unless they are wrapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplexer {
+    /// tmux, which needs `set -g allow-passthrough on` since version 3.3.
+    Tmux,
+    Screen,
+}
+
+/// The multiplexer the program runs in, told by the variables each one sets.
+pub fn multiplexer() -> Option<Multiplexer> {
+    if std::env::var_os("TMUX").is_some() {
+        Some(Multiplexer::Tmux)
+    } else if std::env::var_os("STY").is_some() {
+        Some(Multiplexer::Screen)
+    } else {
+        None
+    }
+}
+
+/// Wraps the escape sequence `seq` so that it reaches the terminal through the
+/// multiplexer the program runs in, if any.
+pub fn passthrough(seq: &[u8]) -> Vec<u8> {
+    wrap_for(multiplexer(), seq)
+}
+
+/// Wraps `seq` in the passthrough sequence of `multiplexer`: a tmux DCS with every ESC
+/// doubled, or as many screen DCS as its length limit asks for.
+///
+/// Screen ends its DCS at the first ST, so the ESC of an ST inside `seq` ends one of
+/// its pieces and the backslash starts the next: the terminal gets both, screen
+/// neither as a pair.
+pub fn wrap_for(multiplexer: Option<Multiplexer>, seq: &[u8]) -> Vec<u8> {
+    match multiplexer {
+        None => seq.to_vec(),
+        Some(Multiplexer::Tmux) => {
+            let mut wrapped = b"\x1bPtmux;".to_vec();
+            for &byte in seq {
+                if byte == 0x1b {
+                    wrapped.push(0x1b);
+                }
+                wrapped.push(byte);
+            }
+            wrapped.extend_from_slice(b"\x1b\\");
+            wrapped
+        }
+        Some(Multiplexer::Screen) => {
+            let mut wrapped = vec![];
+            let mut rest = seq;
+            while !rest.is_empty() {
+                let len = rest.len().min(SCREEN_CHUNK_LEN);
+                let len = rest[..len]
+                    .windows(2)
+                    .position(|pair| pair == b"\x1b\\")
+                    .map_or(len, |st| st + 1);
+                wrapped.extend_from_slice(b"\x1bP");
+                wrapped.extend_from_slice(&rest[..len]);
+                wrapped.extend_from_slice(b"\x1b\\");
+                rest = &rest[len..];
+            }
+            wrapped
+        }
+    }
+}
+
+/// Sends the query `data` to the terminal, through any multiplexer, and returns what
+/// the terminal replies within 100ms.
 pub fn write(data: &[u8]) -> String {
-    let mut stdin = async_stdin().bytes();
-    let mut stdout = stdout().lock().into_raw_mode().unwrap();
-    stdout.write(data);
+    let mut stdout = match stdout().lock().into_raw_mode() {
+        Ok(stdout) => stdout,
+        // Not a terminal, nobody to ask.
+        Err(_) => return String::new(),
+    };
+    let _ = stdout.write(&passthrough(data));
     let _ = stdout.flush();
-    thread::sleep(Duration::from_millis(100));
+    let buf = read_input(Duration::from_millis(100));
+    String::from_utf8(buf).map_or("".into(), |i| i)
+}
+
+/// The controlling terminal, opened once.
+fn tty() -> Option<&'static File> {
+    static TTY: OnceLock<Option<File>> = OnceLock::new();
+    TTY.get_or_init(|| File::open("/dev/tty").ok()).as_ref()
+}
+
+/// Whether the program has a controlling terminal to read replies from, whatever
+/// stdin is.
+pub fn has_tty() -> bool {
+    tty().is_some()
+}
+
+/// Reads whatever the terminal sends within `timeout`.
+///
+/// Replies and keys are read from `/dev/tty` rather than stdin, which may be an image
+/// piped in. Unlike termion's `async_stdin`, no reader thread is left behind to
+/// swallow the input that comes later, such as the keys of an interactive program.
+/// The terminal is expected to be in raw mode.
+pub fn read_input(timeout: Duration) -> Vec<u8> {
+    let fd = match tty() {
+        Some(tty) => tty.as_raw_fd(),
+        None => return vec![],
+    };
+    let deadline = Instant::now() + timeout;
     let mut buf = vec![];
-    while let Some(Ok(v)) = stdin.next() {
-        buf.push(v)
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll(&mut fds, left.as_millis() as i32) {
+            Ok(n) if n > 0 => {
+                let mut chunk = [0; 1024];
+                match nix::unistd::read(fd, &mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+            _ => break,
+        }
+    }
+    buf
+}
+
+/// The terminal's background colour, queried with OSC 11 once and then remembered.
+pub fn background_color() -> Option<[u8; 3]> {
+    static COLOR: OnceLock<Option<[u8; 3]>> = OnceLock::new();
+    *COLOR.get_or_init(|| parse_osc_color(&write(b"\x1b]11;?\x1b\\")))
+}
+
+/// Parses the `rgb:RRRR/GGGG/BBBB` colour of an OSC 10/11 reply, whose channels may
+/// have 1 to 4 hex digits.
+pub fn parse_osc_color(resp: &str) -> Option<[u8; 3]> {
+    let start = resp.find("rgb:")? + 4;
+    let mut channels = resp[start..].splitn(3, '/').map(|hex| {
+        let hex = &hex[..hex.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(hex.len())];
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        Some((value * 255 / max) as u8)
+    });
+    Some([channels.next()??, channels.next()??, channels.next()??])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_background_reply() {
+        assert_eq!(
+            parse_osc_color("\x1b]11;rgb:ffff/8080/0000\x1b\\"),
+            Some([255, 128, 0])
+        );
+        assert_eq!(parse_osc_color("\x1b]11;rgb:f/0/8\x07"), Some([255, 0, 136]));
+        assert_eq!(parse_osc_color(""), None);
+    }
+
+    #[test]
+    fn wrap_for_multiplexers() {
+        let seq = b"\x1b_Ga=q;\x1b\\";
+        assert_eq!(wrap_for(None, seq), seq.to_vec());
+        assert_eq!(
+            wrap_for(Some(Multiplexer::Tmux), seq),
+            b"\x1bPtmux;\x1b\x1b_Ga=q;\x1b\x1b\\\x1b\\".to_vec()
+        );
+        let long = vec![b'x'; SCREEN_CHUNK_LEN + 1];
+        let wrapped = wrap_for(Some(Multiplexer::Screen), &long);
+        assert_eq!(wrapped.len(), long.len() + 2 * 4);
+        assert!(wrapped.starts_with(b"\x1bPxx"));
+        assert!(wrapped.ends_with(b"\x1b\\\x1bPx\x1b\\"));
+    }
+
+    #[test]
+    fn screen_pieces_never_hold_an_st() {
+        let mut seq = b"\x1b_Ga=T,m=1;".to_vec();
+        seq.resize(SCREEN_CHUNK_LEN - 1, b'A');
+        seq.extend_from_slice(b"\x1b\\\x1b_Gm=0;AAAA\x1b\\");
+        let wrapped = wrap_for(Some(Multiplexer::Screen), &seq);
+        let mut inner = &wrapped[2..wrapped.len() - 2];
+        let mut pieces = vec![];
+        while let Some(end) = inner.windows(4).position(|w| w == b"\x1b\\\x1bP") {
+            pieces.push(&inner[..end]);
+            inner = &inner[end + 4..];
+        }
+        pieces.push(inner);
+        for piece in &pieces {
+            assert!(piece.len() <= SCREEN_CHUNK_LEN);
+            assert!(!piece.windows(2).any(|pair| pair == b"\x1b\\"));
+        }
+        assert_eq!(pieces.concat(), seq);
+        assert_eq!(pieces[0].len(), SCREEN_CHUNK_LEN);
+        assert_eq!(pieces[1], b"\\\x1b_Gm=0;AAAA\x1b");
     }
-    String::from_utf8(buf).map_or("".into(), |i| i)
 }
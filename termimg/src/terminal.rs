@@ -0,0 +1,173 @@
+//! Telling which terminal emulator the program runs in, to pick the graphics protocol
+//! it handles best.
+extern crate atty;
+use std::sync::OnceLock;
+
+use crate::term::{has_tty, write};
+
+/// A terminal emulator, by the lowercase name used in [`preferred_protocols`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl TerminalInfo {
+    fn new(name: &str, version: Option<String>) -> TerminalInfo {
+        TerminalInfo {
+            name: name.to_owned(),
+            version: version.filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+/// Best protocols first, for the terminals known to handle one.
+const PREFERENCES: &[(&str, &[&str])] = &[
+    ("kitty", &["kitty"]),
+    ("ghostty", &["kitty"]),
+    ("wezterm", &["iterm", "kitty", "sixel"]),
+    ("konsole", &["kitty", "iterm", "sixel"]),
+    ("iterm2", &["iterm", "sixel"]),
+    ("vscode", &["iterm", "sixel"]),
+    ("mintty", &["iterm", "sixel"]),
+    ("foot", &["sixel"]),
+    ("xterm", &["sixel"]),
+    ("mlterm", &["sixel"]),
+    ("contour", &["sixel"]),
+    ("vte", &["sixel"]),
+    ("alacritty", &[]),
+    ("apple_terminal", &[]),
+];
+
+/// The order protocols are probed in on terminals missing from the table.
+const DEFAULT_PREFERENCE: &[&str] = &["kitty", "iterm", "sixel"];
+
+/// The protocols worth trying on `terminal`, best first. Text mosaics, which work
+/// everywhere, are left out.
+pub fn preferred_protocols(terminal: Option<&TerminalInfo>) -> &'static [&'static str] {
+    terminal
+        .and_then(|t| PREFERENCES.iter().find(|(name, _)| *name == t.name))
+        .map_or(DEFAULT_PREFERENCE, |(_, protocols)| protocols)
+}
+
+/// The terminal the program runs in, found out once from the environment or else by
+/// asking the terminal with XTVERSION and DA2.
+pub fn identify() -> Option<TerminalInfo> {
+    static TERMINAL: OnceLock<Option<TerminalInfo>> = OnceLock::new();
+    TERMINAL
+        .get_or_init(|| {
+            from_env(|var| std::env::var(var).ok()).or_else(|| {
+                // Replies come from /dev/tty, so stdin may well be an image piped in.
+                if !atty::is(atty::Stream::Stdout) || !has_tty() {
+                    return None;
+                }
+                // Both in one go, so that only one timeout is waited for.
+                let reply = write(b"\x1b[>0q\x1b[>c");
+                parse_xtversion(&reply).or_else(|| parse_da2(&reply))
+            })
+        })
+        .clone()
+}
+
+/// Identifies the terminal by the variables it sets, `var` giving their values.
+pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Option<TerminalInfo> {
+    if var("KITTY_WINDOW_ID").is_some() {
+        return Some(TerminalInfo::new("kitty", var("TERM_PROGRAM_VERSION")));
+    }
+    if var("WEZTERM_EXECUTABLE").is_some() {
+        return Some(TerminalInfo::new("wezterm", var("TERM_PROGRAM_VERSION")));
+    }
+    if let Some(version) = var("KONSOLE_VERSION") {
+        return Some(TerminalInfo::new("konsole", Some(version)));
+    }
+    if let Some(program) = var("TERM_PROGRAM") {
+        let name = match program.as_str() {
+            "iTerm.app" => Some("iterm2"),
+            "WezTerm" => Some("wezterm"),
+            "vscode" => Some("vscode"),
+            "mintty" => Some("mintty"),
+            "ghostty" => Some("ghostty"),
+            "Apple_Terminal" => Some("apple_terminal"),
+            // Multiplexers set it to their own name; the terminal is behind them.
+            _ => None,
+        };
+        if let Some(name) = name {
+            return Some(TerminalInfo::new(name, var("TERM_PROGRAM_VERSION")));
+        }
+    }
+    if let Some(version) = var("VTE_VERSION") {
+        return Some(TerminalInfo::new("vte", Some(version)));
+    }
+    None
+}
+
+/// Parses an XTVERSION reply, `DCS > | name(version) ST` or `DCS > | name version ST`.
+pub fn parse_xtversion(reply: &str) -> Option<TerminalInfo> {
+    let start = reply.find("\x1bP>|")? + 4;
+    let end = start + reply[start..].find('\x1b')?;
+    let text = reply[start..end].trim();
+    let (name, version) = match text.find('(') {
+        Some(i) => (&text[..i], text[i + 1..].trim_end_matches(')')),
+        None => text.split_once(' ').unwrap_or((text, "")),
+    };
+    let name = name.trim().to_lowercase();
+    Some(TerminalInfo::new(&name, Some(version.trim().to_owned())))
+}
+
+/// Parses a DA2 reply, `CSI > kind ; version ; ... c`, for the few terminals that
+/// can be told by the kind they report.
+pub fn parse_da2(reply: &str) -> Option<TerminalInfo> {
+    let start = reply.find("\x1b[>")? + 3;
+    let end = start + reply[start..].find('c')?;
+    let mut fields = reply[start..end].split(';');
+    let kind = fields.next()?;
+    let version = fields.next().map(str::to_owned);
+    let name = match kind {
+        "41" => "xterm",
+        "77" => "mintty",
+        _ => return None,
+    };
+    Some(TerminalInfo::new(name, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify_from_env() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            from_env(env(&[("TERM_PROGRAM", "WezTerm"), ("TERM_PROGRAM_VERSION", "2024")])),
+            Some(TerminalInfo::new("wezterm", Some("2024".to_owned())))
+        );
+        assert_eq!(
+            from_env(env(&[("TERM_PROGRAM", "tmux"), ("VTE_VERSION", "7600")])),
+            Some(TerminalInfo::new("vte", Some("7600".to_owned())))
+        );
+        assert_eq!(from_env(env(&[("TERM_PROGRAM", "tmux")])), None);
+    }
+
+    #[test]
+    fn parse_query_replies() {
+        assert_eq!(
+            parse_xtversion("\x1bP>|kitty(0.35.2)\x1b\\\x1b[>1;4000;35c"),
+            Some(TerminalInfo::new("kitty", Some("0.35.2".to_owned())))
+        );
+        assert_eq!(
+            parse_xtversion("\x1bP>|WezTerm 20240203\x1b\\"),
+            Some(TerminalInfo::new("wezterm", Some("20240203".to_owned())))
+        );
+        assert_eq!(
+            parse_da2("\x1b[>41;390;0c"),
+            Some(TerminalInfo::new("xterm", Some("390".to_owned())))
+        );
+        assert_eq!(parse_da2("\x1b[>1;10;0c"), None);
+    }
+}
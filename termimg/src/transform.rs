@@ -0,0 +1,188 @@
+//! Transformations applied to an image before it is laid out: rotating, flipping,
+//! zooming and colour adjustments.
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::utils::{convert_to_rgb_rgba, has_alpha};
+
+/// One step of the pipeline [`apply`] runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    /// Rotates clockwise by this many degrees. Quarter turns are exact; other angles
+    /// grow the image to hold the rotated one, with transparent corners.
+    Rotate(f32),
+    /// Mirrors left to right.
+    FlipHorizontal,
+    /// Mirrors top to bottom.
+    FlipVertical,
+    /// Enlarges the part of the image in a viewport `factor` times smaller than it,
+    /// whose top left corner is at `x`, `y`, back to the size of the image.
+    Zoom { factor: f32, x: u32, y: u32 },
+    Grayscale,
+    Invert,
+    /// Adds this to every colour channel, -255 to 255.
+    Brightness(i32),
+    /// Scales the distance of every channel from mid-grey, in percent: 0 leaves the
+    /// image as it is, negative values lower the contrast.
+    Contrast(f32),
+}
+
+/// Parses a flip axis: `horizontal`, `vertical` or `both`.
+pub fn parse_flip(s: &str) -> Result<Vec<Transform>, String> {
+    match s {
+        "horizontal" | "h" => Ok(vec![Transform::FlipHorizontal]),
+        "vertical" | "v" => Ok(vec![Transform::FlipVertical]),
+        "both" => Ok(vec![Transform::FlipHorizontal, Transform::FlipVertical]),
+        _ => Err(format!("Invalid flip: {}, expected horizontal, vertical or both", s)),
+    }
+}
+
+/// Parses a zoom factor, at least 1.
+pub fn parse_zoom(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(factor) if factor >= 1.0 && factor.is_finite() => Ok(factor),
+        _ => Err(format!("Invalid zoom: {}, expected a factor of at least 1", s)),
+    }
+}
+
+/// Parses a viewport offset, `X,Y` in pixels.
+pub fn parse_offset(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid offset: {}, expected X,Y", s);
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    let x = x.trim().parse().map_err(|_| invalid())?;
+    let y = y.trim().parse().map_err(|_| invalid())?;
+    Ok((x, y))
+}
+
+/// Runs `transforms` on `img` in order. The result is RGB, or RGBA when transparent.
+pub fn apply(img: DynamicImage, transforms: &[Transform]) -> DynamicImage {
+    if transforms.is_empty() {
+        return img;
+    }
+    let img = transforms.iter().fold(img, |img, t| match *t {
+        Transform::Rotate(degrees) => rotate(img, degrees),
+        Transform::FlipHorizontal => img.fliph(),
+        Transform::FlipVertical => img.flipv(),
+        Transform::Zoom { factor, x, y } => zoom(img, factor, x, y),
+        Transform::Grayscale => img.grayscale(),
+        Transform::Invert => {
+            let mut img = img;
+            img.invert();
+            img
+        }
+        Transform::Brightness(value) => img.brighten(value),
+        Transform::Contrast(percent) => img.adjust_contrast(percent),
+    });
+    convert_to_rgb_rgba(img)
+}
+
+fn rotate(img: DynamicImage, degrees: f32) -> DynamicImage {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees == 0.0 {
+        img
+    } else if degrees == 90.0 {
+        img.rotate90()
+    } else if degrees == 180.0 {
+        img.rotate180()
+    } else if degrees == 270.0 {
+        img.rotate270()
+    } else {
+        DynamicImage::ImageRgba8(rotate_by(&img, degrees.to_radians()))
+    }
+}
+
+/// Rotates clockwise by `angle` radians, sampling bilinearly, on a canvas just large
+/// enough for the whole of the rotated image.
+fn rotate_by(img: &DynamicImage, angle: f32) -> RgbaImage {
+    let src = img.to_rgba8();
+    let (w, h) = (src.width() as f32, src.height() as f32);
+    let (sin, cos) = angle.sin_cos();
+    let width = (w * cos.abs() + h * sin.abs()).round().max(1.0);
+    let height = (w * sin.abs() + h * cos.abs()).round().max(1.0);
+    let sample = |x: f32, y: f32| -> [f32; 4] {
+        match (x.round(), y.round()) {
+            (x, y) if x >= 0.0 && y >= 0.0 && x < w && y < h => {
+                src.get_pixel(x as u32, y as u32).0.map(|c| c as f32)
+            }
+            _ => [0.0; 4],
+        }
+    };
+    RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        // Where this pixel comes from, turning the other way around the centres.
+        let dx = x as f32 + 0.5 - width / 2.0;
+        let dy = y as f32 + 0.5 - height / 2.0;
+        let sx = dx * cos + dy * sin + w / 2.0 - 0.5;
+        let sy = -dx * sin + dy * cos + h / 2.0 - 0.5;
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let corners = [
+            (sample(x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (sample(x0 + 1.0, y0), fx * (1.0 - fy)),
+            (sample(x0, y0 + 1.0), (1.0 - fx) * fy),
+            (sample(x0 + 1.0, y0 + 1.0), fx * fy),
+        ];
+        // Weigh colours by their alpha so that the transparent outside does not
+        // darken the edges.
+        let alpha: f32 = corners.iter().map(|(p, weight)| p[3] * weight).sum();
+        if alpha <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let channel = |c: usize| {
+            let sum: f32 = corners.iter().map(|(p, weight)| p[c] * p[3] * weight).sum();
+            (sum / alpha).round().clamp(0.0, 255.0) as u8
+        };
+        Rgba([channel(0), channel(1), channel(2), alpha.round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+fn zoom(img: DynamicImage, factor: f32, x: u32, y: u32) -> DynamicImage {
+    let (w, h) = img.dimensions();
+    let width = ((w as f32 / factor).round() as u32).clamp(1, w.max(1));
+    let height = ((h as f32 / factor).round() as u32).clamp(1, h.max(1));
+    let x = x.min(w - width);
+    let y = y.min(h - height);
+    let zoomed = img.crop_imm(x, y, width, height).resize_exact(w, h, FilterType::Triangle);
+    if has_alpha(&img) {
+        zoomed
+    } else {
+        DynamicImage::ImageRgb8(zoomed.to_rgb8())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_turns_and_flips() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 1, |x, _| {
+            image::Rgb([x as u8 * 255, 0, 0])
+        }));
+        let rotated = apply(img.clone(), &[Transform::Rotate(-270.0)]);
+        assert_eq!(rotated.dimensions(), (1, 2));
+        assert_eq!(rotated.get_pixel(0, 1).0, [255, 0, 0, 255]);
+        let flipped = apply(img, &[Transform::FlipHorizontal]);
+        assert_eq!(flipped.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn arbitrary_rotation_grows_the_canvas() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(10, 10));
+        let rotated = apply(img, &[Transform::Rotate(45.0)]);
+        assert_eq!(rotated.dimensions(), (14, 14));
+        assert_eq!(rotated.get_pixel(0, 0).0[3], 0);
+        assert_eq!(rotated.get_pixel(7, 7).0[3], 255);
+    }
+
+    #[test]
+    fn zoom_keeps_the_size_and_clamps_the_viewport() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(4, 4, |x, _| {
+            image::Rgb([x as u8 * 60, 0, 0])
+        }));
+        let zoomed = apply(img, &[Transform::Zoom { factor: 2.0, x: 100, y: 0 }]);
+        assert_eq!(zoomed.dimensions(), (4, 4));
+        assert_eq!(zoomed.get_pixel(3, 0).0[0], 180);
+        assert!(parse_zoom("0.5").is_err());
+        assert_eq!(parse_offset("3, 4"), Ok((3, 4)));
+    }
+}
@@ -1,11 +1,23 @@
-use image::{DynamicImage, GenericImage};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+
+use image::{DynamicImage, ImageFormat};
 
 use crate::graphic::TerminalSize;
+use crate::metadata;
+use crate::options::{Background, DisplayOptions};
+use crate::term;
+use crate::transform;
+use usvg::SystemFontDB;
+
+/// How many leading bytes are inspected when sniffing the format of some data.
+const SNIFF_LEN: usize = 4096;
 
 #[derive(Debug)]
 pub enum LoadImageError {
     SvgError(String),
     ImageError(image::ImageError),
+    IoError(std::io::Error),
+    UnknownFormat,
 }
 
 impl std::fmt::Display for LoadImageError {
@@ -13,6 +25,8 @@ impl std::fmt::Display for LoadImageError {
         match &self {
             LoadImageError::SvgError(msg) => write!(f, "{}", msg),
             LoadImageError::ImageError(err) => err.fmt(f),
+            LoadImageError::IoError(err) => err.fmt(f),
+            LoadImageError::UnknownFormat => write!(f, "Unknown image format"),
         }
     }
 }
@@ -22,85 +36,507 @@ impl From<image::ImageError> for LoadImageError {
         LoadImageError::ImageError(e)
     }
 }
+impl From<std::io::Error> for LoadImageError {
+    fn from(e: std::io::Error) -> Self {
+        LoadImageError::IoError(e)
+    }
+}
 
-pub fn get_image(path: &String) -> std::result::Result<DynamicImage, LoadImageError> {
-    if path.ends_with(".svg") {
-        let svg_root = usvg::Tree::from_file(path, &usvg::Options::default());
-        if let Err(_) = svg_root {
-            return Err(LoadImageError::SvgError("Failed to load svg".to_string()));
+/// The kind of image some data holds, as told by its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Svg,
+    /// Gzip compressed SVG.
+    Svgz,
+    Raster(ImageFormat),
+}
+
+impl ImageKind {
+    /// Whether this build can decode images of this kind.
+    pub fn decodable(self) -> bool {
+        match self {
+            ImageKind::Svg | ImageKind::Svgz => true,
+            ImageKind::Raster(fmt) => fmt.reading_enabled(),
+        }
+    }
+}
+
+/// Maps a mime type reported by `infer` to the `image` crate's format.
+pub fn image_format_from_mime(mime: &str) -> Option<ImageFormat> {
+    match mime {
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        "image/tiff" => Some(ImageFormat::Tiff),
+        "image/bmp" => Some(ImageFormat::Bmp),
+        "image/vnd.microsoft.icon" => Some(ImageFormat::Ico),
+        "image/avif" => Some(ImageFormat::Avif),
+        _ => None,
+    }
+}
+
+/// Guesses the kind of image from the leading bytes of `data`.
+pub fn sniff_format(data: &[u8]) -> Option<ImageKind> {
+    if let Some(kind) = infer::get(data) {
+        if kind.mime_type() == "application/gzip" {
+            return if is_svgz(data) { Some(ImageKind::Svgz) } else { None };
+        }
+        if let Some(fmt) = image_format_from_mime(kind.mime_type()) {
+            return Some(ImageKind::Raster(fmt));
         }
-        let svg_root = svg_root.unwrap();
-        let svg_image = resvg::render(&svg_root, usvg::FitTo::Width(1000), None);
-        if let Some(svg_image) = svg_image {
-            let mut dyn_img = DynamicImage::new_rgba8(svg_image.width(), svg_image.height());
-            let data = svg_image.data();
-            for x in 0..svg_image.width() {
-                for y in 0..svg_image.height() {
-                    let ind: usize = ((y * svg_image.width() + x) * 4) as usize;
-                    let r = data[ind];
-                    let g = data[ind + 1];
-                    let b = data[ind + 2];
-                    let a = data[ind + 3];
-
-                    dyn_img.put_pixel(x, y, image::Rgba([r, g, b, a]))
-                }
+    }
+    if is_svg(data) {
+        return Some(ImageKind::Svg);
+    }
+    // Formats `infer` knows nothing about: QOI, PNM, DDS, HDR, OpenEXR, farbfeld...
+    if let Ok(fmt) = image::guess_format(data) {
+        return Some(ImageKind::Raster(fmt));
+    }
+    if cfg!(feature = "tga") && is_tga(data) {
+        return Some(ImageKind::Raster(ImageFormat::Tga));
+    }
+    None
+}
+
+/// Sniffs the format of the file at `path` from its leading bytes, without decoding it.
+pub fn sniff_file<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Option<ImageKind>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(sniff_format(&head))
+}
+
+/// TGA has no magic number either; accept a header whose fields all hold values the
+/// format allows and agree with one another: a colour map only for colour-mapped
+/// images, a pixel depth that suits the image type, a size and no reserved bits set.
+fn is_tga(data: &[u8]) -> bool {
+    if data.len() < 18 {
+        return false;
+    }
+    let color_map = data[1];
+    let image_type = data[2];
+    let depth = data[16];
+    let descriptor = data[17];
+    let width = u16::from_le_bytes([data[12], data[13]]);
+    let height = u16::from_le_bytes([data[14], data[15]]);
+    let types_agree = match image_type {
+        1 | 9 => color_map == 1 && matches!(depth, 8 | 16),
+        2 | 10 => color_map == 0 && matches!(depth, 15 | 16 | 24 | 32),
+        3 | 11 => color_map == 0 && matches!(depth, 8 | 16),
+        _ => false,
+    };
+    // Without a colour map, its specification is all zeros.
+    let no_map_spec = color_map == 1 || data[3..8].iter().all(|&b| b == 0);
+    types_agree
+        && no_map_spec
+        && width > 0
+        && height > 0
+        && descriptor & 0xc0 == 0
+        && descriptor & 0x0f <= 8
+}
+
+/// Whether gzip compressed `data` holds an SVG document, judging by the head of what
+/// it decompresses to. `data` may be cut short, as when only its head was read.
+fn is_svgz(data: &[u8]) -> bool {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let _ = flate2::read::GzDecoder::new(data)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head);
+    is_svg(&head)
+}
+
+/// SVG has no magic number, so look for an `<svg` element among the leading bytes,
+/// which may be preceded by a BOM, an XML declaration, a doctype or comments.
+fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(SNIFF_LEN)];
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start();
+    text.starts_with('<') && text.contains("<svg")
+}
+
+fn render_svg(data: &[u8], opts: &DisplayOptions) -> Result<DynamicImage, LoadImageError> {
+    let mut svg_opts = usvg::Options::default();
+    if let Some(dpi) = opts.dpi {
+        svg_opts.dpi = dpi;
+    }
+    // Loading system fonts is slow, only do it when the document may contain text.
+    if sniff_format(data) == Some(ImageKind::Svgz) || contains(data, b"<text") {
+        svg_opts.fontdb.load_system_fonts();
+    }
+    let svg_root = usvg::Tree::from_data(data, &svg_opts)
+        .map_err(|_| LoadImageError::SvgError("Failed to load svg".to_string()))?;
+    let svg_size = svg_root.svg_node().size.to_screen_size();
+    let (width, _) = opts.fit_size(svg_size.width(), svg_size.height());
+    let background = opts
+        .svg_background
+        .map(|[r, g, b]| usvg::Color::new(r, g, b));
+    let svg_image = resvg::render(&svg_root, usvg::FitTo::Width(width), background)
+        .ok_or_else(|| LoadImageError::SvgError("Failed to render svg".to_string()))?;
+    let (width, height) = (svg_image.width(), svg_image.height());
+    image::RgbaImage::from_raw(width, height, svg_image.take())
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| LoadImageError::SvgError("Failed to render svg".to_string()))
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Decodes an image held in memory, telling its format by content.
+pub fn get_image_from_bytes(data: &[u8]) -> Result<DynamicImage, LoadImageError> {
+    get_image_from_bytes_with_options(data, &DisplayOptions::default())
+}
+
+/// Like [`get_image_from_bytes`], rendering SVGs at the size `opts` lays them out at.
+pub fn get_image_from_bytes_with_options(
+    data: &[u8],
+    opts: &DisplayOptions,
+) -> Result<DynamicImage, LoadImageError> {
+    get_image_from_reader_with_options(Cursor::new(data), opts)
+}
+
+/// Decodes a raster image from `reader`, which starts at `start`, then applies its ICC
+/// profile and EXIF orientation as `opts` ask. The reader is rewound for each pass.
+fn decode_raster<R: BufRead + Seek>(
+    mut reader: R,
+    start: u64,
+    fmt: ImageFormat,
+    opts: &DisplayOptions,
+) -> Result<DynamicImage, LoadImageError> {
+    let icc = if opts.color_manage {
+        let icc = metadata::icc_profile(&mut reader, fmt);
+        reader.seek(SeekFrom::Start(start))?;
+        icc
+    } else {
+        None
+    };
+    let img = match &icc {
+        Some(icc) => match metadata::decode_cmyk_jpeg(&mut reader, icc) {
+            Some(img) => img,
+            None => {
+                reader.seek(SeekFrom::Start(start))?;
+                metadata::to_srgb(decode(&mut reader, fmt)?, icc)
             }
-            return Ok(dyn_img);
+        },
+        None => decode(&mut reader, fmt)?,
+    };
+    if opts.auto_orient {
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(metadata::apply_orientation(img, metadata::orientation(&mut reader)))
+    } else {
+        Ok(img)
+    }
+}
+
+/// Decodes `reader` as `fmt`. Radiance HDR is kept as floats, which `image` would clip
+/// to 8-bit, for [`tone_map`] to bring the highlights into range.
+fn decode<R: BufRead + Seek>(reader: R, fmt: ImageFormat) -> Result<DynamicImage, image::ImageError> {
+    #[cfg(feature = "hdr")]
+    if fmt == ImageFormat::Hdr {
+        let decoder = image::codecs::hdr::HdrDecoder::new(reader)?;
+        let meta = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        let floats = pixels.iter().flat_map(|p| p.0).collect();
+        return image::Rgb32FImage::from_raw(meta.width, meta.height, floats)
+            .map(DynamicImage::ImageRgb32F)
+            .ok_or_else(|| {
+                image::ImageError::Limits(image::error::LimitError::from_kind(
+                    image::error::LimitErrorKind::DimensionError,
+                ))
+            });
+    }
+    image::io::Reader::with_format(reader, fmt).decode()
+}
+
+/// Decodes an image from `reader`, telling its format by content.
+///
+/// Raster images are decoded straight from the reader; SVGs are read to the end first.
+pub fn get_image_from_reader<R: Read + Seek>(reader: R) -> Result<DynamicImage, LoadImageError> {
+    get_image_from_reader_with_options(reader, &DisplayOptions::default())
+}
+
+/// Like [`get_image_from_reader`], rendering SVGs at the size `opts` lays them out at.
+pub fn get_image_from_reader_with_options<R: Read + Seek>(
+    reader: R,
+    opts: &DisplayOptions,
+) -> Result<DynamicImage, LoadImageError> {
+    let mut reader = BufReader::new(reader);
+    let start = reader.stream_position()?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    (&mut reader).take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    reader.seek(SeekFrom::Start(start))?;
+    match sniff_format(&head) {
+        Some(ImageKind::Svg) | Some(ImageKind::Svgz) => {
+            let mut data = vec![];
+            reader.read_to_end(&mut data)?;
+            render_svg(&data, opts)
         }
+        Some(ImageKind::Raster(fmt)) => decode_raster(reader, start, fmt, opts),
+        None => Err(LoadImageError::UnknownFormat),
+    }
+}
+
+pub fn get_image(path: &String) -> std::result::Result<DynamicImage, LoadImageError> {
+    get_image_with_options(path, &DisplayOptions::default())
+}
+
+pub fn get_image_with_options(
+    path: &String,
+    opts: &DisplayOptions,
+) -> std::result::Result<DynamicImage, LoadImageError> {
+    let file = std::fs::File::open(path)?;
+    match get_image_from_reader_with_options(file, opts) {
+        Err(LoadImageError::UnknownFormat) if path.ends_with(".svg") || path.ends_with(".svgz") => {
+            render_svg(&std::fs::read(path)?, opts)
+        }
+        Err(LoadImageError::UnknownFormat) => Ok(image::open(path)?),
+        r => r,
     }
-    Ok(image::open(path)?)
 }
 pub fn convert_to_rgb_rgba(img: DynamicImage) -> DynamicImage {
     match img.color() {
         image::ColorType::Rgb8 => img,
         image::ColorType::Rgba8 => img,
+        image::ColorType::Rgb32F | image::ColorType::Rgba32F => tone_map(&img),
         _ => {
             if has_alpha(&img) {
-                DynamicImage::ImageRgba8(img.to_rgba())
+                DynamicImage::ImageRgba8(img.to_rgba8())
                 // img.to_rgba()
                 //     .map(|img| DynamicImage::ImageRgba8(img.clone()))
             } else {
-                DynamicImage::ImageRgb8(img.to_rgb())
+                DynamicImage::ImageRgb8(img.to_rgb8())
             }
         }
     }
 }
 
 pub fn prepare_img(path: &String, size: &Option<TerminalSize>) -> Result<DynamicImage, String> {
-    let size = size.as_ref().unwrap_or(&TerminalSize {
-        width: 0,
-        height: 0,
-        cols: 0,
-        rows: 0,
-    });
-    let width = size.width;
-    let height = size.height;
-    get_image(&path)
+    prepare_img_with_options(path, &DisplayOptions::new(size.clone()))
+}
+
+/// Loads the image at `path` and scales it to the size `opts` lays it out at.
+pub fn prepare_img_with_options(path: &String, opts: &DisplayOptions) -> Result<DynamicImage, String> {
+    get_image_with_options(path, opts)
         .map_err(|e| e.to_string())
-        .map(convert_to_rgb_rgba)
-        .and_then(|img| {
-            if width == 0 || height == 0 {
-                return Ok(img);
-            }
-            let (w, _) = image::GenericImageView::dimensions(&img);
-            if w > (width as u32) {
-                let scale = (w as f32) / (width as f32);
-                let new_height = (scale * (height as f32)) as u32;
-                Ok(img.resize(
-                    width as u32,
-                    new_height,
-                    image::imageops::FilterType::Nearest,
-                ))
-            } else {
-                Ok(img)
-            }
+        .and_then(|img| prepare_decoded(img, opts))
+}
+
+/// Like [`prepare_img_with_options`], for an image already read into memory.
+pub fn prepare_img_from_bytes(data: &[u8], opts: &DisplayOptions) -> Result<DynamicImage, String> {
+    get_image_from_bytes_with_options(data, opts)
+        .map_err(|e| e.to_string())
+        .and_then(|img| prepare_decoded(img, opts))
+}
+
+/// Crops, transforms and scales an image just decoded as `opts` asks.
+pub fn prepare_decoded(img: DynamicImage, opts: &DisplayOptions) -> Result<DynamicImage, String> {
+    crop_image(convert_to_rgb_rgba(img), opts)
+        .map(|img| transform::apply(img, &opts.transforms))
+        .map(|img| fit_image(img, opts))
+}
+
+/// Cuts the region `opts` asks for out of `img`, keeping what of it lies in the image.
+pub fn crop_image(img: DynamicImage, opts: &DisplayOptions) -> Result<DynamicImage, String> {
+    let region = match opts.crop {
+        Some(region) => region,
+        None => return Ok(img),
+    };
+    region
+        .within(img.width(), img.height())
+        .map(|r| img.crop_imm(r.x, r.y, r.width, r.height))
+        .ok_or_else(|| {
+            format!(
+                "Region {}x{}+{}+{} lies outside the {}x{} image",
+                region.width,
+                region.height,
+                region.x,
+                region.y,
+                img.width(),
+                img.height()
+            )
         })
 }
+
+/// Scales `img` to the size `opts` lays it out at and flattens it against the
+/// background `opts` asks for.
+pub fn fit_image(img: DynamicImage, opts: &DisplayOptions) -> DynamicImage {
+    let (w, h) = (img.width(), img.height());
+    let (width, height) = opts.fit_size(w, h);
+    let img = if (width, height) == (w, h) {
+        img
+    } else {
+        img.resize_exact(width, height, opts.filter)
+    };
+    match opts.background {
+        Some(background) => composite(img, background),
+        None => img,
+    }
+}
+
+/// Blends a transparent image over `background`, leaving an opaque RGB image.
+pub fn composite(img: DynamicImage, background: Background) -> DynamicImage {
+    if !has_alpha(&img) {
+        return img;
+    }
+    let color = match background {
+        Background::Color(color) => color,
+        Background::Terminal => term::background_color().unwrap_or([0, 0, 0]),
+        Background::Checkerboard(_) => [0, 0, 0],
+    };
+    let rgba = img.to_rgba8();
+    let flat = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let bg = match background {
+            Background::Checkerboard(size) if (x / size + y / size) % 2 == 0 => [204; 3],
+            Background::Checkerboard(_) => [153; 3],
+            _ => color,
+        };
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |fg: u8, bg: u8| ((fg as u32 * a as u32 + bg as u32 * (255 - a as u32)) / 255) as u8;
+        image::Rgb([blend(r, bg[0]), blend(g, bg[1]), blend(b, bg[2])])
+    });
+    DynamicImage::ImageRgb8(flat)
+}
+
+/// Maps linear high dynamic range pixels (HDR, OpenEXR) to 8-bit sRGB.
+///
+/// Uses the Reinhard operator on luminance so hues are kept while highlights are
+/// compressed instead of clipped.
+pub fn tone_map(img: &DynamicImage) -> DynamicImage {
+    fn encode(c: f32) -> u8 {
+        let c = c.clamp(0.0, 1.0);
+        let c = if c <= 0.003_130_8 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (c * 255.0).round() as u8
+    }
+    let mut out = image::RgbaImage::new(img.width(), img.height());
+    for (src, dst) in img.to_rgba32f().pixels().zip(out.pixels_mut()) {
+        let [r, g, b, a] = src.0;
+        let lum = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let scale = if lum > 0.0 { 1.0 / (1.0 + lum) } else { 1.0 };
+        dst.0 = [
+            encode(r * scale),
+            encode(g * scale),
+            encode(b * scale),
+            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ];
+    }
+    if has_alpha(img) {
+        DynamicImage::ImageRgba8(out)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(out).to_rgb8())
+    }
+}
+
 pub fn has_alpha(img: &DynamicImage) -> bool {
-    use image::ColorType;
-    let color = img.color();
-    color == image::ColorType::La8
-        || color == ColorType::Rgba8
-        || color == ColorType::La16
-        || color == ColorType::Bgra8
+    img.color().has_alpha()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_svg_by_content() {
+        let svg = b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<!-- logo -->\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        assert_eq!(sniff_format(svg), Some(ImageKind::Svg));
+        assert_eq!(sniff_format(b"<html><body></body></html>"), None);
+    }
+
+    #[test]
+    fn sniff_gzip_by_content() {
+        use std::io::Write;
+        let gzip = |data: &[u8]| {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let svg = gzip(b"<?xml version=\"1.0\"?><svg xmlns=\"http://www.w3.org/2000/svg\"/>");
+        assert_eq!(sniff_format(&svg), Some(ImageKind::Svgz));
+        assert_eq!(sniff_format(&gzip(b"just some text")), None);
+    }
+
+    #[test]
+    #[cfg(feature = "tga")]
+    fn sniff_tga_by_header() {
+        let mut tga = vec![0u8; 18];
+        tga[2] = 2;
+        tga[12] = 4;
+        tga[14] = 4;
+        tga[16] = 24;
+        assert_eq!(sniff_format(&tga), Some(ImageKind::Raster(ImageFormat::Tga)));
+        // Right types, but a colour map on a true-colour image and reserved bits set.
+        let mut noise = tga.clone();
+        noise[1] = 1;
+        noise[17] = 0xff;
+        assert_eq!(sniff_format(&noise), None);
+        tga[12] = 0;
+        assert_eq!(sniff_format(&tga), None);
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn reader_decodes_from_its_position() {
+        let mut png = b"junk".to_vec();
+        let mut encoded = vec![];
+        DynamicImage::ImageRgb8(image::RgbImage::new(3, 2))
+            .write_to(&mut Cursor::new(&mut encoded), image::ImageOutputFormat::Png)
+            .unwrap();
+        png.extend_from_slice(&encoded);
+        let mut reader = Cursor::new(png);
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        let img = get_image_from_reader(reader).unwrap();
+        assert_eq!((img.width(), img.height()), (3, 2));
+    }
+
+    #[test]
+    fn sniff_raster_by_magic() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(sniff_format(png), Some(ImageKind::Raster(ImageFormat::Png)));
+        assert_eq!(sniff_format(b"qoif\0\0\0\x01"), Some(ImageKind::Raster(ImageFormat::Qoi)));
+    }
+
+    #[test]
+    fn svg_rendered_at_fit_size() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100"/>"#;
+        let mut opts = DisplayOptions::new(Some(TerminalSize {
+            width: 50,
+            height: 40,
+            cols: 5,
+            rows: 2,
+        }));
+        opts.svg_background = Some([255, 0, 0]);
+        let img = get_image_from_bytes_with_options(svg, &opts).unwrap();
+        assert_eq!((img.width(), img.height()), (50, 25));
+        assert_eq!(img.to_rgba8().get_pixel(0, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn composite_blends_over_background() {
+        let img = image::RgbaImage::from_pixel(2, 1, image::Rgba([255, 255, 255, 0]));
+        let flat = composite(DynamicImage::ImageRgba8(img), Background::Color([10, 20, 30]));
+        assert_eq!(flat.color(), image::ColorType::Rgb8);
+        assert_eq!(flat.to_rgb8().get_pixel(1, 0).0, [10, 20, 30]);
+    }
+
+    #[test]
+    #[cfg(feature = "hdr")]
+    fn hdr_file_is_tone_mapped() {
+        // A flat 2x1 Radiance file: RGBE pixels of 8.0 and 0.25.
+        let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        hdr.extend_from_slice(&[128, 128, 128, 132, 128, 128, 128, 127]);
+        let img = get_image_from_bytes(&hdr).unwrap();
+        assert_eq!(img.color(), image::ColorType::Rgb32F);
+        let img = convert_to_rgb_rgba(img);
+        assert_eq!(img.color(), image::ColorType::Rgb8);
+        let bright = img.to_rgb8().get_pixel(0, 0).0;
+        let dim = img.to_rgb8().get_pixel(1, 0).0;
+        assert!(bright[0] > 200 && bright[0] < 255);
+        assert!(dim[0] < bright[0]);
+    }
 }